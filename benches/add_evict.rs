@@ -0,0 +1,49 @@
+//! counts allocator calls made by `add` on a full cache
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use lru_rs::lru::LRUCache;
+
+const CAP: u64 = 1_000;
+const ADDS: u64 = 1_000_000;
+
+struct CountingAlloc;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn main() {
+    let mut lru: LRUCache<u64, u64> = LRUCache::new(CAP as usize);
+    for i in 0..CAP {
+        lru.add(i, i);
+    }
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in CAP..CAP + ADDS {
+        black_box(lru.add(i, i));
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+
+    println!("add on a full cache: {:?} per add", elapsed / ADDS as u32);
+    println!("allocations: {allocs} for {ADDS} adds");
+}
@@ -0,0 +1,35 @@
+//! compares the cloning `get` with `get_ref` on large values
+
+use std::{hint::black_box, time::Instant};
+
+use lru_rs::lru::LRUCache;
+
+const ENTRIES: u64 = 1_000;
+const ROUNDS: u64 = 100;
+
+fn main() {
+    let mut lru: LRUCache<u64, Vec<u8>> = LRUCache::new(ENTRIES as usize);
+    for i in 0..ENTRIES {
+        lru.add(i, vec![0; 4096]);
+    }
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for i in 0..ENTRIES {
+            black_box(lru.get(&i));
+        }
+    }
+    let get = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for i in 0..ENTRIES {
+            black_box(lru.get_ref(&i));
+        }
+    }
+    let get_ref = start.elapsed();
+
+    let lookups = (ENTRIES * ROUNDS) as u32;
+    println!("get:     {:?} per lookup", get / lookups);
+    println!("get_ref: {:?} per lookup", get_ref / lookups);
+}
@@ -0,0 +1,325 @@
+//! Fixed capacity LRU cache
+
+use std::{borrow::Borrow, fmt, mem::MaybeUninit};
+
+/// marks the absence of a slot in the links
+const NIL: usize = usize::MAX;
+
+/// LRU cache with a capacity of `N` entries that never allocates.
+///
+/// Entries live in a fixed `[MaybeUninit<(K, V)>; N]` arena and are linked by
+/// slot index instead of by pointer. Without a hashmap, lookups scan the
+/// entries from the MRU end, so it suits small `N`.
+pub struct ArrayLRUCache<K: Eq, V: Clone, const N: usize> {
+    entries: [MaybeUninit<(K, V)>; N],
+    prev: [usize; N],
+    next: [usize; N],
+    head: usize,
+    tail: usize,
+    free: usize,
+    len: usize,
+}
+
+impl<K: Eq, V: Clone, const N: usize> fmt::Debug for ArrayLRUCache<K, V, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArrayLRUCache")
+            .field("cap", &N)
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Eq, V: Clone, const N: usize> Default for ArrayLRUCache<K, V, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq, V: Clone, const N: usize> ArrayLRUCache<K, V, N> {
+    /// creates new empty instance, every slot starts on the free list
+    /// # `Returns`
+    /// - Self
+    pub fn new() -> Self {
+        Self {
+            entries: [const { MaybeUninit::uninit() }; N],
+            prev: [NIL; N],
+            next: std::array::from_fn(|i| if i + 1 < N { i + 1 } else { NIL }),
+            head: NIL,
+            tail: NIL,
+            free: if N > 0 { 0 } else { NIL },
+            len: 0,
+        }
+    }
+
+    /// number of entries in the cache
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// checks if the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// adds new key to the LRU cache, an existing key gets its value updated
+    /// and is promoted
+    /// # `params`
+    /// `key`: key of the entry
+    /// `value`: value of the entry
+    ///
+    /// # `Returns`
+    /// None if no keys were evicted, (key, value) if a key was evicted
+    pub fn add(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(index) = self.find(&key) {
+            unsafe { self.entries[index].assume_init_mut().1 = value };
+            self.promote(index);
+            return None;
+        }
+        if N == 0 {
+            return Some((key, value));
+        }
+
+        if self.free == NIL {
+            let index = self.tail;
+            self.detach(index);
+            let evicted = std::mem::replace(
+                unsafe { self.entries[index].assume_init_mut() },
+                (key, value),
+            );
+            self.attach_front(index);
+            return Some(evicted);
+        }
+
+        let index = self.free;
+        self.free = self.next[index];
+        self.entries[index].write((key, value));
+        self.attach_front(index);
+        self.len += 1;
+        None
+    }
+
+    /// get value associated with the key
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise value associated with the key
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.find(key)?;
+        self.promote(index);
+        Some(unsafe { self.entries[index].assume_init_ref().1.clone() })
+    }
+
+    /// get value associated with the key without promoting it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise reference to the value
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.find(key)?;
+        Some(unsafe { &self.entries[index].assume_init_ref().1 })
+    }
+
+    /// checks if the key exists in the cache without promoting it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// removes the key from the cache
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the removed value
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.find(key)?;
+        self.detach(index);
+        let (_, value) = unsafe { self.entries[index].assume_init_read() };
+        self.next[index] = self.free;
+        self.free = index;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// slot of the key, scanning from the MRU end
+    fn find<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let mut curr = self.head;
+        while curr != NIL {
+            if unsafe { self.entries[curr].assume_init_ref().0.borrow() } == key {
+                return Some(curr);
+            }
+            curr = self.next[curr];
+        }
+        None
+    }
+
+    /// moves the slot to the MRU end
+    fn promote(&mut self, index: usize) {
+        if self.head != index {
+            self.detach(index);
+            self.attach_front(index);
+        }
+    }
+
+    /// unlinks the slot from its neighbours, the slot must be in the list
+    fn detach(&mut self, index: usize) {
+        let (prev, next) = (self.prev[index], self.next[index]);
+        match prev {
+            NIL => self.head = next,
+            prev => self.next[prev] = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.prev[next] = prev,
+        }
+    }
+
+    /// links the slot at the MRU end
+    fn attach_front(&mut self, index: usize) {
+        self.prev[index] = NIL;
+        self.next[index] = self.head;
+        match self.head {
+            NIL => self.tail = index,
+            head => self.prev[head] = index,
+        }
+        self.head = index;
+    }
+}
+
+impl<K: Eq, V: Clone, const N: usize> Drop for ArrayLRUCache<K, V, N> {
+    fn drop(&mut self) {
+        let mut curr = self.head;
+        while curr != NIL {
+            unsafe { self.entries[curr].assume_init_drop() };
+            curr = self.next[curr];
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::array::ArrayLRUCache;
+    use std::rc::Rc;
+
+    #[test]
+    fn make_lru() {
+        let mut lru: ArrayLRUCache<u64, u64, 5> = ArrayLRUCache::new();
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+        assert_eq!(lru.get(&1), Some(1));
+        assert_eq!(lru.get(&2), Some(2));
+
+        lru.add(3, 3);
+        lru.add(4, 4);
+        lru.add(5, 5);
+        assert_eq!(lru.len(), 5);
+
+        // 1 is the least recently used entry
+        assert_eq!(lru.add(6, 6), Some((1, 1)));
+        assert_eq!(lru.add(7, 7), Some((2, 2)));
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut lru: ArrayLRUCache<u64, u64, 1> = ArrayLRUCache::new();
+
+        let res = lru.add(1, 1);
+        assert!(res.is_none());
+
+        let res = lru.add(2, 2);
+        assert_eq!(Some((1, 1)), res);
+
+        assert_eq!(lru.get(&1), None);
+        assert_eq!(lru.get(&2), Some(2));
+    }
+
+    #[test]
+    fn peek_does_not_promote() {
+        let mut lru: ArrayLRUCache<u64, u64, 5> = ArrayLRUCache::new();
+        for i in 1..=5 {
+            lru.add(i, i * 10);
+        }
+
+        assert_eq!(lru.peek(&1), Some(&10));
+        assert!(lru.contains_key(&1));
+        assert_eq!(lru.add(6, 60), Some((1, 10)));
+        assert_eq!(lru.peek(&1), None);
+    }
+
+    #[test]
+    fn update_promotes_existing_key() {
+        let mut lru: ArrayLRUCache<u64, u64, 5> = ArrayLRUCache::new();
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+
+        assert_eq!(lru.add(1, 100), None);
+        assert_eq!(lru.len(), 5);
+        assert_eq!(lru.add(6, 6), Some((2, 2)));
+        assert_eq!(lru.get(&1), Some(100));
+    }
+
+    #[test]
+    fn remove_frees_a_slot() {
+        let mut lru: ArrayLRUCache<u64, u64, 5> = ArrayLRUCache::new();
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+
+        assert_eq!(lru.remove(&3), Some(3));
+        assert_eq!(lru.remove(&3), None);
+        assert_eq!(lru.len(), 4);
+        assert_eq!(lru.add(6, 6), None);
+        assert_eq!(lru.add(7, 7), Some((1, 1)));
+
+        while let Some(key) = (1..=7).find(|key| lru.contains_key(key)) {
+            lru.remove(&key);
+        }
+        assert!(lru.is_empty());
+        assert_eq!(lru.add(8, 8), None);
+        assert_eq!(lru.get(&8), Some(8));
+    }
+
+    #[test]
+    fn zero_capacity_rejects_entries() {
+        let mut lru: ArrayLRUCache<u64, u64, 0> = ArrayLRUCache::new();
+
+        assert_eq!(lru.add(1, 1), Some((1, 1)));
+        assert_eq!(lru.get(&1), None);
+    }
+
+    #[test]
+    fn drop_releases_remaining_entries() {
+        let value = Rc::new(());
+        {
+            let mut lru: ArrayLRUCache<u64, Rc<()>, 5> = ArrayLRUCache::new();
+            for i in 0..7 {
+                lru.add(i, Rc::clone(&value));
+            }
+            lru.remove(&6);
+            assert_eq!(Rc::strong_count(&value), 5);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,210 @@
+//! CLOCK cache
+
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+/// Slot of the clock ring holding one entry
+#[derive(Debug)]
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    referenced: bool,
+}
+
+/// CLOCK (second-chance) approximation of an LRU cache.
+///
+/// Lookups only set a referenced bit instead of relinking the entry, and
+/// eviction sweeps a hand over the ring clearing bits until it finds an
+/// unreferenced entry.
+#[derive(Debug)]
+pub struct ClockCache<K: Hash + Eq + Clone, V: Clone> {
+    hashmap: HashMap<K, usize>,
+    slots: Vec<Option<Slot<K, V>>>,
+    free: Vec<usize>,
+    hand: usize,
+    cap: usize,
+    len: usize,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ClockCache<K, V> {
+    /// creates new instance of CLOCK cache with the capacity
+    /// # `Arguments`
+    /// - `cap`-> capacity
+    /// # `Returns`
+    /// - Self
+    pub fn new(cap: usize) -> Self {
+        Self {
+            hashmap: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            hand: 0,
+            cap,
+            len: 0,
+        }
+    }
+
+    /// adds new key to the CLOCK cache, an existing key gets its value
+    /// updated and is marked as referenced
+    /// # `params`
+    /// `key`: key of the entry
+    /// `value`: value of the entry
+    ///
+    /// # `Returns`
+    /// None if no keys were evicted, (key, value) if a key was evicted
+    pub fn add(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(&index) = self.hashmap.get(&key) {
+            let slot = self.slots[index].as_mut().unwrap();
+            slot.value = value;
+            slot.referenced = true;
+            return None;
+        }
+        if self.cap == 0 {
+            return Some((key, value));
+        }
+
+        let slot = Slot {
+            key: key.clone(),
+            value,
+            referenced: false,
+        };
+        if self.len < self.cap {
+            let index = match self.free.pop() {
+                Some(index) => {
+                    self.slots[index] = Some(slot);
+                    index
+                }
+                None => {
+                    self.slots.push(Some(slot));
+                    self.slots.len() - 1
+                }
+            };
+            self.hashmap.insert(key, index);
+            self.len += 1;
+            return None;
+        }
+
+        let index = self.sweep();
+        let evicted = self.slots[index].replace(slot).unwrap();
+        self.hashmap.remove(&evicted.key);
+        self.hashmap.insert(key, index);
+        Some((evicted.key, evicted.value))
+    }
+
+    /// get value associated with the key, marking it as referenced
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise value associated with the key
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = *self.hashmap.get(key)?;
+        let slot = self.slots[index].as_mut().unwrap();
+        slot.referenced = true;
+        Some(slot.value.clone())
+    }
+
+    /// get value associated with the key without marking it as referenced
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise reference to the value
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = *self.hashmap.get(key)?;
+        self.slots[index].as_ref().map(|slot| &slot.value)
+    }
+
+    /// checks if the key exists in the cache without marking it as referenced
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hashmap.contains_key(key)
+    }
+
+    /// removes the key from the cache
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the removed value
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.hashmap.remove(key)?;
+        let slot = self.slots[index].take().unwrap();
+        self.free.push(index);
+        self.len -= 1;
+        Some(slot.value)
+    }
+
+    /// advances the hand, giving referenced entries a second chance, until
+    /// it rests on an unreferenced entry and returns its slot index
+    fn sweep(&mut self) -> usize {
+        loop {
+            let index = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            if let Some(slot) = self.slots[index].as_mut() {
+                if !slot.referenced {
+                    return index;
+                }
+                slot.referenced = false;
+            }
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::clock::ClockCache;
+
+    #[test]
+    fn test_eviction() {
+        let mut clock: ClockCache<u64, u64> = ClockCache::new(1);
+
+        let res = clock.add(1, 1);
+        assert!(res.is_none());
+
+        let res = clock.add(2, 2);
+        assert_eq!(Some((1, 1)), res);
+
+        assert_eq!(clock.get(&1), None);
+        assert_eq!(clock.get(&2), Some(2));
+    }
+
+    #[test]
+    fn referenced_entry_survives_a_sweep() {
+        let mut clock: ClockCache<u64, u64> = ClockCache::new(3);
+
+        clock.add(1, 1);
+        clock.add(2, 2);
+        clock.add(3, 3);
+        clock.get(&1);
+
+        assert_eq!(clock.add(4, 4), Some((2, 2)));
+        assert!(clock.contains_key(&1));
+        assert_eq!(clock.add(5, 5), Some((3, 3)));
+        assert_eq!(clock.add(6, 6), Some((1, 1)));
+    }
+
+    #[test]
+    fn remove_frees_a_slot() {
+        let mut clock: ClockCache<u64, u64> = ClockCache::new(2);
+
+        clock.add(1, 1);
+        clock.add(2, 2);
+        assert_eq!(clock.remove(&1), Some(1));
+        assert_eq!(clock.add(3, 3), None);
+        assert_eq!(clock.peek(&2), Some(&2));
+        assert_eq!(clock.peek(&3), Some(&3));
+    }
+}
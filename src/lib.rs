@@ -1,3 +1,5 @@
 //! lib.rs
 
+pub mod array;
+pub mod clock;
 pub mod lru;
@@ -0,0 +1,62 @@
+//! Builder
+
+use std::hash::Hash;
+
+use crate::lru::LRUCache;
+
+/// Builder of an `LRUCache`, bundling the configuration and warm-up of the
+/// cache into one flow
+#[derive(Debug, Clone)]
+pub struct LRUCacheBuilder<K: Hash + Eq + Clone, V: Clone> {
+    cap: usize,
+    seed: Vec<(K, V)>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LRUCacheBuilder<K, V> {
+    /// creates new builder of a cache with the capacity
+    /// # `Arguments`
+    /// - `cap`-> capacity
+    /// # `Returns`
+    /// - Self
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            seed: Vec::new(),
+        }
+    }
+
+    /// entries the built cache starts with, replacing any earlier seed
+    /// # `Arguments`
+    /// - `entries` -> entries in MRU -> LRU order, cloned. The ones past the
+    ///   capacity are dropped.
+    pub fn seed(&mut self, entries: &[(K, V)]) -> &mut Self {
+        self.seed = entries.to_vec();
+        self
+    }
+
+    /// builds the cache
+    /// # `Returns`
+    /// - the configured cache, holding the seed entries
+    pub fn build(&self) -> LRUCache<K, V> {
+        LRUCache::from_ordered(self.cap, self.seed.clone())
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::lru::{LRUCache, LRUCacheBuilder};
+
+    #[test]
+    fn seeded_build() {
+        let mut lru: LRUCache<u64, u64> = LRUCacheBuilder::new(3)
+            .seed(&[(1, 10), (2, 20), (3, 30), (4, 40)])
+            .build();
+
+        assert_eq!(lru.to_index_ordered(), vec![(1, 10), (2, 20), (3, 30)]);
+        assert_eq!(lru.iter().len(), 3);
+        assert_eq!(lru.add(5, 50), Some((3, 30)));
+
+        let empty: LRUCache<u64, u64> = LRUCacheBuilder::new(3).build();
+        assert_eq!(empty.iter().len(), 0);
+    }
+}
@@ -0,0 +1,75 @@
+//! Errors
+
+use std::{error::Error, fmt};
+
+/// Errors returned by the fallible operations of the LRU cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LruError {
+    /// the cache was given a capacity of zero
+    ZeroCapacity,
+    /// the entry alone weighs more than the weight cap
+    ItemTooLarge,
+    /// the cache is full and every entry is pinned
+    AllPinned,
+}
+
+impl fmt::Display for LruError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LruError::ZeroCapacity => write!(f, "capacity must be greater than zero"),
+            LruError::ItemTooLarge => write!(f, "entry weighs more than the weight cap"),
+            LruError::AllPinned => write!(f, "cache is full and every entry is pinned"),
+        }
+    }
+}
+
+impl Error for LruError {}
+
+/// Error of `LRUCache::try_insert` when the key is already present, holds
+/// the rejected entry and the value already in the cache
+#[derive(Debug, PartialEq, Eq)]
+pub struct OccupiedError<'a, K, V> {
+    /// key that was passed to `try_insert`
+    pub key: K,
+    /// value that was not inserted
+    pub value: V,
+    /// value already associated with the key
+    pub existing: &'a V,
+}
+
+impl<K, V> fmt::Display for OccupiedError<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key is already present in the cache")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> Error for OccupiedError<'_, K, V> {}
+
+/// Error returned by `LRUCache::try_insert`, holds the entry that was not
+/// inserted
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryInsertError<'a, K, V> {
+    /// the key is already present
+    Occupied(OccupiedError<'a, K, V>),
+    /// the entry can never fit the limits of the cache, `error` is
+    /// `LruError::ZeroCapacity` or `LruError::ItemTooLarge`
+    Rejected {
+        /// key that was passed to `try_insert`
+        key: K,
+        /// value that was not inserted
+        value: V,
+        /// why the entry does not fit
+        error: LruError,
+    },
+}
+
+impl<K, V> fmt::Display for TryInsertError<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryInsertError::Occupied(err) => err.fmt(f),
+            TryInsertError::Rejected { error, .. } => error.fmt(f),
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> Error for TryInsertError<'_, K, V> {}
@@ -0,0 +1,17 @@
+//! Metrics hooks
+
+/// Receives the events of an LRU cache, e.g. to feed an external metrics
+/// backend. Every method defaults to doing nothing.
+pub trait Metrics {
+    /// a lookup found the key
+    fn on_hit(&mut self) {}
+
+    /// a lookup did not find the key
+    fn on_miss(&mut self) {}
+
+    /// a new entry was inserted
+    fn on_insert(&mut self) {}
+
+    /// an entry was evicted
+    fn on_evict(&mut self) {}
+}
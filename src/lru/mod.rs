@@ -2,29 +2,178 @@
 
 #![allow(dead_code)]
 
-use std::{collections::HashMap, hash::Hash, ptr::NonNull};
+mod builder;
+mod error;
+mod metrics;
+mod namespaced;
+mod normalized;
+mod op;
+mod timer;
+mod window;
+
+pub use builder::LRUCacheBuilder;
+pub use error::{LruError, OccupiedError, TryInsertError};
+pub use metrics::Metrics;
+pub use namespaced::NamespacedLRUCache;
+pub use normalized::NormalizedLRUCache;
+pub use op::Op;
+pub use timer::{OpKind, Timer};
+
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, TryReserveError},
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    ptr::NonNull,
+    rc::{self, Rc},
+    sync::{self, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::lru::window::HitWindow;
+
+/// Weigher used by the weighted mode to compute the weight of an entry
+type Weigher<K, V> = Rc<dyn Fn(&K, &V) -> usize>;
+
+/// Callback receiving the entries evicted from the cache
+type OnEvict<K, V> = Box<dyn FnMut(K, V)>;
+
+/// Callback receiving the old and the new capacity of the cache
+type OnResize = Box<dyn FnMut(usize, usize)>;
+
+/// Pointer to the key stored in a node, the hashmap is keyed by it so every
+/// key is stored only once
+struct KeyRef<K> {
+    key: *const K,
+}
+
+impl<K: Hash> Hash for KeyRef<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        unsafe { (*self.key).hash(state) }
+    }
+}
+
+impl<K: PartialEq> PartialEq for KeyRef<K> {
+    fn eq(&self, other: &Self) -> bool {
+        unsafe { *self.key == *other.key }
+    }
+}
+
+impl<K: Eq> Eq for KeyRef<K> {}
+
+/// Borrowed form of a key, lets the hashmap be queried by any `Q` the key
+/// borrows as
+#[repr(transparent)]
+struct KeyWrapper<Q: ?Sized>(Q);
+
+impl<Q: ?Sized> KeyWrapper<Q> {
+    fn from_ref(key: &Q) -> &Self {
+        unsafe { &*(key as *const Q as *const KeyWrapper<Q>) }
+    }
+}
+
+impl<Q: Hash + ?Sized> Hash for KeyWrapper<Q> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<Q: PartialEq + ?Sized> PartialEq for KeyWrapper<Q> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<Q: Eq + ?Sized> Eq for KeyWrapper<Q> {}
+
+impl<K: Borrow<Q>, Q: ?Sized> Borrow<KeyWrapper<Q>> for KeyRef<K> {
+    fn borrow(&self) -> &KeyWrapper<Q> {
+        unsafe { KeyWrapper::from_ref((*self.key).borrow()) }
+    }
+}
 
 /// Node for the linked list that is used to bookkeep the LRU cache
 #[derive(Debug)]
 pub struct Node<K: Hash + Eq + Clone, V: Clone> {
     key: Option<K>,
     value: Option<V>,
+    weight: usize,
+    pinned: bool,
+    expires_at: Option<Instant>,
+    accessed_at: Option<Instant>,
     prev: Option<NonNull<Node<K, V>>>,
     next: Option<NonNull<Node<K, V>>>,
 }
 
 /// LRU cache struct
-#[derive(Debug)]
 pub struct LRUCache<K: Hash + Eq + Clone, V: Clone> {
-    hashmap: HashMap<K, NonNull<Node<K, V>>>,
+    hashmap: HashMap<KeyRef<K>, NonNull<Node<K, V>>>,
     cap: usize,
     len: usize,
+    max_weight: usize,
+    weight: usize,
+    weigher: Option<Weigher<K, V>>,
+    auto_shrink: bool,
+    flush_on_drop: bool,
+    track_access_time: bool,
+    on_evict: Option<OnEvict<K, V>>,
+    on_resize: Option<OnResize>,
+    metrics: Option<Box<dyn Metrics>>,
+    window: Option<HitWindow>,
+    timer: Option<Box<dyn Timer>>,
     head: NonNull<Node<K, V>>,
     tail: NonNull<Node<K, V>>,
 }
 
+/// Outcome of `LRUCache::try_get`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetResult<V> {
+    /// the key exists, holds a clone of its value
+    Hit(V),
+    /// the key existed but its time to live ran out, the entry was removed
+    Expired,
+    /// the key does not exist
+    Miss,
+}
+
+/// Outcome of `LRUCache::add_report`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddReport<K, V> {
+    /// the key was absent and the entry was linked in
+    pub inserted: bool,
+    /// the key was present, holds the value it was updated from
+    pub old_value: Option<V>,
+    /// entries evicted by the insertion in eviction order, unless an eviction
+    /// callback took them. A rejected new entry that can never fit the limits
+    /// shows up here with `inserted` false.
+    pub evicted: Vec<(K, V)>,
+}
+
+/// LRU cache of shared values, accessors hand out cheap `Arc` clones
+pub type ArcLRUCache<K, T> = LRUCache<K, Arc<T>>;
+
+impl<K: Hash + Eq + Clone, V: Clone> fmt::Debug for LRUCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LRUCache")
+            .field("cap", &self.cap)
+            .field("len", &self.len)
+            .field("max_weight", &self.max_weight)
+            .field("weight", &self.weight)
+            .field("auto_shrink", &self.auto_shrink)
+            .field("flush_on_drop", &self.flush_on_drop)
+            .field("track_access_time", &self.track_access_time)
+            .finish_non_exhaustive()
+    }
+}
+
 impl<K: Hash + Eq + Clone, V: Clone> LRUCache<K, V> {
-    /// creates new instance of LRU cache with the capacity
+    /// creates new instance of LRU cache with the capacity, the canonical
+    /// constructor. Any capacity up to `usize::MAX` is accepted, at
+    /// `usize::MAX` the count never triggers an eviction. In the weighted mode
+    /// an entry that would push the total weight past `usize::MAX` does not
+    /// fit, like one past the weight cap. The hashmap allocates
+    /// lazily as entries come in, see `with_capacity` to size it up front.
     /// # `Arguments`
     /// - `cap`-> capacity
     /// # `Returns`
@@ -33,6 +182,10 @@ impl<K: Hash + Eq + Clone, V: Clone> LRUCache<K, V> {
         let default_node = Node {
             key: None,
             value: None,
+            weight: 0,
+            pinned: false,
+            expires_at: None,
+            accessed_at: None,
             prev: None,
             next: None,
         };
@@ -40,6 +193,10 @@ impl<K: Hash + Eq + Clone, V: Clone> LRUCache<K, V> {
         let default_node = Node {
             key: None,
             value: None,
+            weight: 0,
+            pinned: false,
+            expires_at: None,
+            accessed_at: None,
             prev: None,
             next: None,
         };
@@ -59,169 +216,5044 @@ impl<K: Hash + Eq + Clone, V: Clone> LRUCache<K, V> {
             hashmap: HashMap::new(),
             cap,
             len: 0,
+            max_weight: usize::MAX,
+            weight: 0,
+            weigher: None,
+            auto_shrink: false,
+            flush_on_drop: false,
+            track_access_time: false,
+            on_evict: None,
+            on_resize: None,
+            metrics: None,
+            window: None,
+            timer: None,
             head,
             tail,
         }
     }
 
-    /// adds new key to the LRU cache
+    /// creates new instance of LRU cache like `new`, sizing the hashmap for
+    /// `cap` entries so filling the cache never rehashes
+    /// # `Arguments`
+    /// - `cap`-> capacity, should be small enough to allocate
+    /// # `Returns`
+    /// - Self
+    pub fn with_capacity(cap: usize) -> Self {
+        let mut lru = Self::new(cap);
+        lru.hashmap.reserve(cap);
+        lru
+    }
+
+    /// creates new instance of LRU cache with the capacity
+    /// # `Arguments`
+    /// - `cap`-> capacity
+    /// # `Returns`
+    /// - Self, or `LruError::ZeroCapacity` if `cap` is zero
+    pub fn try_new(cap: usize) -> Result<Self, LruError> {
+        if cap == 0 {
+            return Err(LruError::ZeroCapacity);
+        }
+        Ok(Self::new(cap))
+    }
+
+    /// creates new instance of LRU cache holding the entries in the given
+    /// recency order
+    /// # `Arguments`
+    /// - `cap`-> capacity
+    /// - `entries` -> entries in MRU -> LRU order, the ones past `cap` are dropped
+    /// # `Returns`
+    /// - Self
+    pub fn from_ordered(cap: usize, entries: Vec<(K, V)>) -> Self {
+        let mut lru = Self::new(cap);
+        for (key, value) in entries.into_iter().take(cap).rev() {
+            lru.add(key, value);
+        }
+        lru
+    }
+
+    /// creates new instance of LRU cache bounded both by entry count and total weight
+    /// # `Arguments`
+    /// - `max_entries` -> maximum number of entries
+    /// - `max_weight` -> maximum sum of the weights of all entries
+    /// - `weigher` -> computes the weight of an entry when it is added
+    /// # `Returns`
+    /// - Self, evicting from the LRU end whenever either limit is exceeded
+    pub fn with_limits<F>(max_entries: usize, max_weight: usize, weigher: F) -> Self
+    where
+        F: Fn(&K, &V) -> usize + 'static,
+    {
+        let mut lru = Self::new(max_entries);
+        lru.max_weight = max_weight;
+        lru.weigher = Some(Rc::new(weigher));
+        lru
+    }
+
+    /// registers a callback receiving every evicted entry, evictions are then
+    /// no longer returned by `add`. When one operation evicts several entries
+    /// the callback sees them least recently used first.
+    /// # `Arguments`
+    /// - `f` -> called with the key and the value of each evicted entry
+    pub fn set_on_evict<F>(&mut self, f: F)
+    where
+        F: FnMut(K, V) + 'static,
+    {
+        self.on_evict = Some(Box::new(f));
+    }
+
+    /// removes the eviction callback, evictions are returned by `add` again
+    pub fn clear_on_evict(&mut self) {
+        self.on_evict = None;
+    }
+
+    /// makes dropping the cache hand every remaining entry to the eviction
+    /// callback in LRU -> MRU order, so a write-back cache flushes on drop.
+    /// Has no effect without a callback.
+    /// # `Arguments`
+    /// - `enabled` -> whether to flush the entries on drop
+    pub fn set_flush_on_drop(&mut self, enabled: bool) {
+        self.flush_on_drop = enabled;
+    }
+
+    /// changes the capacity, evicting from the LRU end when shrinking. This
+    /// is the single shrink path, `resize` forwards to it.
+    /// # `Arguments`
+    /// - `new_cap` -> new capacity
+    /// # `Returns`
+    /// - evicted entries in LRU -> MRU order, empty when an eviction
+    ///   callback is set as the entries are handed to it in the same order
+    pub fn set_capacity(&mut self, new_cap: usize) -> Vec<(K, V)> {
+        let start = self.start_timer();
+        self.change_cap(new_cap);
+        let mut evicted = Vec::new();
+        while self.len > self.cap {
+            let Some((key, value)) = self.pop_lru() else {
+                break;
+            };
+            evicted.extend(self.evict(key, value));
+        }
+        self.debug_check();
+        self.record_time(OpKind::Resize, start);
+        evicted
+    }
+
+    /// changes the capacity, same as `set_capacity`
+    pub fn resize(&mut self, new_cap: usize) -> Vec<(K, V)> {
+        self.set_capacity(new_cap)
+    }
+
+    /// attaches a metrics implementor notified of hits, misses, inserts and
+    /// evictions
+    /// # `Arguments`
+    /// - `metrics` -> receives the events of the cache
+    pub fn with_metrics<M>(mut self, metrics: M) -> Self
+    where
+        M: Metrics + 'static,
+    {
+        self.metrics = Some(Box::new(metrics));
+        self
+    }
+
+    /// attaches a timer receiving the duration of the operations below, each
+    /// call is recorded once:
+    /// - `OpKind::Add` -> `add` and `add_with_ttl`
+    /// - `OpKind::Get` -> `get`, `get_or`, `try_get`, `get_ref`, `get_mut`,
+    ///   `get_or_insert_with`, `try_get_or_insert_with`,
+    ///   `get_or_insert_with_evicted`, `get_or_insert_with_ttl`,
+    ///   `get_or_insert_default` and `get_mut_or_insert_with`
+    /// - `OpKind::Peek` -> `peek`
+    /// - `OpKind::Remove` -> `remove`
+    /// - `OpKind::Clear` -> `clear`
+    /// - `OpKind::Resize` -> `set_capacity` and `resize`
+    ///
+    /// `get_or_insert_with_async` records the `get` and the `add` it is made
+    /// of, the other methods are not timed. Without a timer the clock is
+    /// never read.
+    /// # `Arguments`
+    /// - `timer` -> receives the durations of the operations
+    pub fn with_timer<T>(mut self, timer: T) -> Self
+    where
+        T: Timer + 'static,
+    {
+        self.timer = Some(Box::new(timer));
+        self
+    }
+
+    /// tracks the outcome of the last `size` lookups for `recent_hit_ratio`,
+    /// which reflects the current workload better than lifetime counts.
+    /// Setting a window discards the recorded outcomes, zero stops tracking.
+    /// # `Arguments`
+    /// - `size` -> number of lookups in the window
+    pub fn set_window(&mut self, size: usize) {
+        self.window = (size > 0).then(|| HitWindow::new(size));
+    }
+
+    /// share of hits among the lookups in the window set by `set_window`
+    /// # `Returns`
+    /// - ratio between 0 and 1, 0 when no window is set or nothing was
+    ///   looked up yet
+    pub fn recent_hit_ratio(&self) -> f64 {
+        self.window.as_ref().map_or(0.0, HitWindow::hit_ratio)
+    }
+
+    /// registers a callback notified whenever `set_capacity`, `resize`,
+    /// `grow` or `clear_and_resize` changes the capacity
+    /// # `Arguments`
+    /// - `f` -> called with the old and the new capacity
+    pub fn on_resize<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(usize, usize) + 'static,
+    {
+        self.on_resize = Some(Box::new(f));
+        self
+    }
+
+    /// increases the capacity by `additional`, saturating at `usize::MAX`
+    /// # `Arguments`
+    /// - `additional` -> number of entries to make room for
+    pub fn grow(&mut self, additional: usize) {
+        self.change_cap(self.cap.saturating_add(additional));
+        self.debug_check();
+    }
+
+    /// total weight of the entries currently in the cache
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    /// adds new key to the LRU cache, an existing key gets its value updated
+    /// and is promoted
     /// # `params`
     /// `key`: key of the entry
     /// `value`: value of the entry
     ///
     /// # `Returns`
-    /// None if no keys were evicted, (key, value) if a key was evicted
+    /// None if no keys were evicted, (key, value) if a key was evicted.
+    /// If the weight cap forces several evictions, the least recently used
+    /// one is returned and the others are dropped. A new entry, or a new value
+    /// of an existing key, that can never fit the limits is returned as is
+    /// and leaves the cache untouched.
     pub fn add(&mut self, key: K, value: V) -> Option<(K, V)> {
-        let node = Node {
-            key: Some(key.clone()),
-            value: Some(value),
-            prev: None,
-            next: None,
-        };
+        let start = self.start_timer();
+        let evicted = self.add_expiring(key, value, None);
+        self.record_time(OpKind::Add, start);
+        evicted
+    }
 
-        let node = Box::into_raw(Box::new(node));
-        unsafe {
-            let node = NonNull::new_unchecked(node);
-            (*node.as_ptr()).prev = Some(self.head);
+    /// adds new key to the LRU cache like `add`, the entry expires after
+    /// `ttl`. Expired entries are treated as absent by lookups, which remove
+    /// them lazily without going through the eviction callback.
+    /// # `params`
+    /// `key`: key of the entry
+    /// `value`: value of the entry
+    /// `ttl`: time to live of the entry
+    ///
+    /// # `Returns`
+    /// same as `add`
+    pub fn add_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<(K, V)> {
+        let start = self.start_timer();
+        let evicted = self.add_expiring(key, value, Some(Instant::now() + ttl));
+        self.record_time(OpKind::Add, start);
+        evicted
+    }
 
-            (*node.as_ptr()).next = (*self.head.as_ptr()).next;
-            let head_next = (*self.head.as_ptr()).next.unwrap();
+    /// adds new key to the LRU cache with an optional expiry
+    fn add_expiring(&mut self, key: K, value: V, expires_at: Option<Instant>) -> Option<(K, V)> {
+        let mut evicted = None;
+        self.insert_entry(key, value, expires_at, &mut |key, value| {
+            evicted.get_or_insert((key, value));
+        });
+        self.debug_check();
+        evicted
+    }
 
-            (*head_next.as_ptr()).prev = Some(node);
-            (*self.head.as_ptr()).next = Some(node);
+    /// adds new key to the LRU cache, handing every eviction the callback
+    /// does not take to `sink`
+    /// # `Returns`
+    /// - None if the new entry can never fit and was rejected, otherwise the
+    ///   replaced value of an existing key
+    fn insert_entry(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+        sink: &mut dyn FnMut(K, V),
+    ) -> Option<Option<V>> {
+        let weight = self.weigh(&key, &value);
+        if self.cap == 0 || weight > self.max_weight {
+            if let Some((key, value)) = self.evict(key, value) {
+                sink(key, value);
+            }
+            return None;
+        }
+        if let Some(&node) = self.hashmap.get(KeyWrapper::from_ref(&key)) {
+            let old_value = unsafe {
+                self.reweigh(node, weight, sink);
+                (*node.as_ptr()).expires_at = expires_at;
 
-            self.hashmap.insert(key, node);
+                self.detach(node);
+                self.attach_front(node);
+                self.touch(node);
+                (*node.as_ptr()).value.replace(value)
+            };
+            self.trim(sink);
+            return Some(old_value);
         }
+        if self.len == self.cap
+            && let Some(victim) = self.lru_victim()
+        {
+            let victim_weight = unsafe { (*victim.as_ptr()).weight };
+            if (self.weight - victim_weight)
+                .checked_add(weight)
+                .is_some_and(|total| total <= self.max_weight)
+            {
+                let (key, value) = self.recycle(victim, key, value, weight);
+                unsafe { (*victim.as_ptr()).expires_at = expires_at };
+                if let Some((key, value)) = self.evict(key, value) {
+                    sink(key, value);
+                }
+                return Some(None);
+            }
+        }
+        self.make_room(weight, sink);
+        if self.weight.checked_add(weight).is_none() {
+            if let Some((key, value)) = self.evict(key, value) {
+                sink(key, value);
+            }
+            return None;
+        }
+        let node = self.insert_front(key, value, weight);
+        unsafe { (*node.as_ptr()).expires_at = expires_at };
+        Some(None)
+    }
 
-        self.len += 1;
-        if self.len > self.cap {
-            unsafe {
-                let last_entry = (*self.tail.as_ptr()).prev.unwrap();
-                let key = (*last_entry.as_ptr()).key.clone().unwrap();
-                let value = (*last_entry.as_ptr()).value.clone().unwrap();
-                let last_prev = (*last_entry.as_ptr()).prev.unwrap();
-                (*self.tail.as_ptr()).prev = Some(last_prev);
-                (*last_prev.as_ptr()).next = Some(self.tail);
-                self.hashmap.remove(&key);
+    /// adds new key to the LRU cache like `add`, describing everything the
+    /// insertion did
+    /// # `params`
+    /// `key`: key of the entry
+    /// `value`: value of the entry
+    ///
+    /// # `Returns`
+    /// the `AddReport` of the insertion
+    pub fn add_report(&mut self, key: K, value: V) -> AddReport<K, V> {
+        let mut evicted = Vec::new();
+        let outcome = self.insert_entry(key, value, None, &mut |key, value| {
+            evicted.push((key, value))
+        });
+        self.debug_check();
+        AddReport {
+            inserted: matches!(outcome, Some(None)),
+            old_value: outcome.flatten(),
+            evicted,
+        }
+    }
+
+    /// adds all entries in order like `add`
+    /// # `Arguments`
+    /// - `entries` -> entries to add, the last one ends up MRU
+    /// # `Returns`
+    /// - every entry evicted by the batch, in eviction order, unless an
+    ///   eviction callback takes them
+    pub fn add_many(&mut self, entries: Vec<(K, V)>) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        for (key, value) in entries {
+            self.insert_entry(key, value, None, &mut |key, value| {
+                evicted.push((key, value))
+            });
+        }
+        self.debug_check();
+        evicted
+    }
 
-                let boxed = Box::from_raw(last_entry.as_ptr());
-                _ = boxed;
+    /// inserts an already ordered recency list, oldest first: the first entry
+    /// ends up LRU among the batch and the last one MRU. This is the order
+    /// `extend` and `add_many` produce, and the reverse of `from_ordered` and
+    /// `LRUCacheBuilder::seed`, which take the MRU entry first. Evicted
+    /// entries go to the eviction callback or are dropped.
+    /// # `Arguments`
+    /// - `entries` -> entries in LRU -> MRU order
+    pub fn insert_ordered(&mut self, entries: Vec<(K, V)>) {
+        for (key, value) in entries {
+            self.insert_entry(key, value, None, &mut |_, _| {});
+        }
+        self.debug_check();
+    }
 
-                return Some((key, value));
+    /// adds new key to the LRU cache at a given recency position instead of
+    /// the MRU end, an existing key is replaced and moved there, staying
+    /// pinned if it was
+    /// # `Arguments`
+    /// - `key` -> key of the entry
+    /// - `value` -> value of the entry
+    /// - `pos` -> recency index, 0 is MRU and `len` or above is just before
+    ///   the tail
+    /// # `Returns`
+    /// - same as `add`, evictions still come from the LRU end
+    pub fn add_at_position(&mut self, key: K, value: V, pos: usize) -> Option<(K, V)> {
+        let weight = self.weigh(&key, &value);
+        if self.cap == 0 || weight > self.max_weight {
+            self.debug_check();
+            return self.evict(key, value);
+        }
+        let mut pinned = false;
+        if let Some(&node) = self.hashmap.get(KeyWrapper::from_ref(&key)) {
+            unsafe {
+                pinned = (*node.as_ptr()).pinned;
+                self.unlink_entry(node);
             }
         }
-        None
+        let mut evicted = None;
+        self.make_room(weight, &mut |key, value| {
+            evicted.get_or_insert((key, value));
+        });
+        if self.weight.checked_add(weight).is_none() {
+            self.debug_check();
+            let rejected = self.evict(key, value);
+            return evicted.or(rejected);
+        }
+        let node = self.insert_front(key, value, weight);
+        unsafe {
+            (*node.as_ptr()).pinned = pinned;
+            self.detach(node);
+            self.attach_at(node, pos.min(self.len - 1));
+        }
+        self.debug_check();
+        evicted
+    }
+
+    /// adds new key to the LRU cache like `add`, failing instead of silently
+    /// rejecting the entry or growing past the limits
+    /// # `params`
+    /// `key`: key of the entry
+    /// `value`: value of the entry
+    ///
+    /// # `Returns`
+    /// the result of `add`, or `LruError::ZeroCapacity` if the capacity is
+    /// zero, `LruError::ItemTooLarge` if the entry weighs more than the weight
+    /// cap and `LruError::AllPinned` if the cache is full of pinned entries
+    pub fn try_add(&mut self, key: K, value: V) -> Result<Option<(K, V)>, LruError> {
+        if self.cap == 0 {
+            return Err(LruError::ZeroCapacity);
+        }
+        if self.weigh(&key, &value) > self.max_weight {
+            return Err(LruError::ItemTooLarge);
+        }
+        if self.len >= self.cap
+            && !self.hashmap.contains_key(KeyWrapper::from_ref(&key))
+            && self.lru_victim().is_none()
+        {
+            return Err(LruError::AllPinned);
+        }
+        Ok(self.add(key, value))
     }
 
     /// get value associated with the key
     /// # `Arguments`
-    /// - `key` -> key of the mapping
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
     /// # `Returns`
     /// - None if key not exist, otherwise value associated with the key
-    pub fn get(&mut self, key: K) -> Option<V> {
-        let value = self.hashmap.get(&key);
+    pub fn get<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let start = self.start_timer();
+        let value = self
+            .get_node(key)
+            .map(|node| unsafe { (*node.as_ptr()).value.clone().unwrap() });
+        self.record_time(OpKind::Get, start);
+        value
+    }
 
-        if let Some(value) = value {
-            unsafe {
-                let prev = (*value.as_ptr()).prev.unwrap();
-                let next = (*value.as_ptr()).next.unwrap();
+    /// get value associated with the key, falling back to `default` on a miss
+    /// without inserting it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// - `default` -> value returned when the key is absent
+    /// # `Returns`
+    /// - clone of the promoted value on a hit, otherwise `default`
+    pub fn get_or<Q>(&mut self, key: &Q, default: V) -> V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// get value associated with the key like `get`, telling an expired entry
+    /// apart from an absent one
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - Hit with the value, Expired if the entry expired and was removed,
+    ///   Miss if key not exist
+    pub fn try_get<Q>(&mut self, key: &Q) -> GetResult<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let start = self.start_timer();
+        let expired = self
+            .hashmap
+            .get(KeyWrapper::from_ref(key))
+            .is_some_and(|node| unsafe { self.is_expired(*node) });
+        let result = match self.lookup(key) {
+            Some(node) => unsafe {
+                self.promote(node);
+                GetResult::Hit((*node.as_ptr()).value.clone().unwrap())
+            },
+            None if expired => GetResult::Expired,
+            None => GetResult::Miss,
+        };
+        self.debug_check();
+        self.record_time(OpKind::Get, start);
+        result
+    }
 
-                (*prev.as_ptr()).next = Some(next);
-                (*next.as_ptr()).prev = Some(prev);
+    /// get reference to the value associated with the key, promoting it
+    /// without cloning the value
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise reference to the value
+    pub fn get_ref<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let start = self.start_timer();
+        let node = self.get_node(key);
+        self.record_time(OpKind::Get, start);
+        node.map(|node| unsafe { (*node.as_ptr()).value.as_ref().unwrap() })
+    }
 
-                (*value.as_ptr()).prev = Some(self.head);
-                (*value.as_ptr()).next = (*self.head.as_ptr()).next;
-                let head_next = (*self.head.as_ptr()).next.unwrap();
+    /// get mutable reference to the value associated with the key, promoting
+    /// it. In the weighted mode call `recompute_weight` after a mutation that
+    /// changes the weight.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise mutable reference to the value
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let start = self.start_timer();
+        let node = self.get_node(key);
+        self.record_time(OpKind::Get, start);
+        node.map(|node| unsafe { (*node.as_ptr()).value.as_mut().unwrap() })
+    }
 
-                (*head_next.as_ptr()).prev = Some(*value);
-                (*self.head.as_ptr()).next = Some(*value);
+    /// finds and promotes the node of the key, the untimed core of the
+    /// promoting lookups
+    fn get_node<Q>(&mut self, key: &Q) -> Option<NonNull<Node<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.lookup(key)?;
+        unsafe { self.promote(node) };
+        self.debug_check();
+        Some(node)
+    }
 
-                let value = (*value.as_ptr()).value.clone();
-                return value;
+    /// get mutable reference to the value associated with the key without
+    /// promoting it, see `get_mut`
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise mutable reference to the value
+    pub fn peek_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.hashmap.get(KeyWrapper::from_ref(key))?;
+        self.debug_check();
+        unsafe {
+            if self.is_expired(node) {
+                return None;
             }
+            (*node.as_ptr()).value.as_mut()
         }
-        None
     }
 
-    /// get first entry of the LRU cache
-    pub fn get_first(&mut self) -> V {
+    /// re-runs the weigher on one entry after its value was mutated in place,
+    /// evicting from the LRU end if the weight cap is now exceeded. An entry
+    /// that alone weighs more than the cap is evicted on its own.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - whether the key was found
+    pub fn recompute_weight<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(&node) = self.hashmap.get(KeyWrapper::from_ref(key)) else {
+            return false;
+        };
         unsafe {
-            let next = (*self.head.as_ptr()).next.unwrap();
-
-            let value = (*next.as_ptr()).value.clone();
-
-            value.unwrap()
+            let entry = &*node.as_ptr();
+            let weight = self.weigh(entry.key.as_ref().unwrap(), entry.value.as_ref().unwrap());
+            if weight > self.max_weight {
+                self.evict_node(node);
+                return true;
+            }
+            self.reweigh(node, weight, &mut |_, _| {});
         }
+        self.trim(&mut |_, _| {});
+        self.debug_check();
+        true
     }
 
-    /// get last entry of the LRU cache
-    pub fn get_last(&mut self) -> V {
+    /// re-runs the weigher on every entry, see `recompute_weight`
+    pub fn recompute_all_weights(&mut self) {
         unsafe {
-            let prev = (*self.tail.as_ptr()).prev.unwrap();
-
-            let value = (*prev.as_ptr()).value.clone();
-
-            value.unwrap()
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let entry = &*curr.as_ptr();
+                let weight = self.weigh(entry.key.as_ref().unwrap(), entry.value.as_ref().unwrap());
+                if weight > self.max_weight {
+                    let next = entry.next.unwrap();
+                    self.evict_node(curr);
+                    curr = next;
+                    continue;
+                }
+                self.reweigh(curr, weight, &mut |_, _| {});
+                // read after the reweigh, which may have evicted the next entry
+                curr = (*curr.as_ptr()).next.unwrap();
+            }
         }
+        self.trim(&mut |_, _| {});
+        self.debug_check();
     }
-}
 
-impl<K: Hash + Eq + Clone, V: Clone> Drop for LRUCache<K, V> {
-    fn drop(&mut self) {
-        let mut curr = self.head;
-        loop {
-            unsafe {
-                let next = (*curr.as_ptr()).next;
-                if next.is_none() {
-                    return;
-                }
-                let next = next.unwrap();
-                let boxed_c = Box::from_raw(curr.as_ptr());
-                _ = boxed_c;
-                curr = next;
+    /// get value associated with the key, promoting it only when `cond`
+    /// accepts the value
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// - `cond` -> decides whether the lookup counts as an access
+    /// # `Returns`
+    /// - None if key not exist, otherwise value associated with the key
+    pub fn get_if<Q, F>(&mut self, key: &Q, cond: F) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> bool,
+    {
+        let node = self.lookup(key)?;
+        unsafe {
+            let value = (*node.as_ptr()).value.as_ref().unwrap();
+            if cond(value) {
+                self.promote(node);
             }
+            self.debug_check();
+            (*node.as_ptr()).value.clone()
         }
     }
-}
 
-mod tests {
-    #![allow(unused_imports)]
-    use crate::lru::LRUCache;
+    /// consumes the entry when `cond` accepts its value, otherwise the entry
+    /// is kept and promoted
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// - `cond` -> decides whether the entry is removed
+    /// # `Returns`
+    /// - the removed value, None if key not exist or the entry was kept
+    pub fn get_and_remove_if<Q, F>(&mut self, key: &Q, cond: F) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> bool,
+    {
+        let node = self.lookup(key)?;
+        unsafe {
+            if cond((*node.as_ptr()).value.as_ref().unwrap()) {
+                return self.remove(key);
+            }
+            self.promote(node);
+        }
+        self.debug_check();
+        None
+    }
 
-    #[test]
-    fn make_lru() {
-        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+    /// get value associated with the key without promoting it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise reference to the value
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let start = self.start_timer();
+        let value = self
+            .hashmap
+            .get(KeyWrapper::from_ref(key))
+            .filter(|node| unsafe { !self.is_expired(**node) })
+            .and_then(|node| unsafe { (*node.as_ptr()).value.as_ref() });
+        self.record_time(OpKind::Peek, start);
+        value
+    }
 
-        lru.add(1, 1);
-        lru.add(2, 2);
+    /// checks if the key exists in the cache without promoting it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hashmap
+            .get(KeyWrapper::from_ref(key))
+            .is_some_and(|node| unsafe { !self.is_expired(*node) })
+    }
 
-        assert_eq!(lru.get_first(), 2);
-        lru.get(1);
-        assert_eq!(lru.get_first(), 1);
+    /// checks if the key exists and has not expired, without promoting it or
+    /// removing an expired entry. Same as `contains_key`, which already skips
+    /// expired entries, named for freshness probes in TTL caches.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn is_fresh<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.contains_key(key)
+    }
 
-        lru.get(2);
-        assert_eq!(lru.get_first(), 2);
+    /// removes the key from the cache
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the removed value
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let start = self.start_timer();
+        let value = self.remove_entry(key).map(|(_, value)| value);
+        self.record_time(OpKind::Remove, start);
+        value
+    }
 
-        lru.add(3, 3);
-        assert_eq!(lru.get_first(), 3);
+    /// removes the key from the cache, same as `remove`
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the removed value
+    pub fn take<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove(key)
     }
 
-    #[test]
-    fn test_eviction() {
-        let mut lru: LRUCache<u64, u64> = LRUCache::new(1);
+    /// removes the key from the cache
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the removed (key, value)
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.hashmap.remove(KeyWrapper::from_ref(key))?;
+        unsafe {
+            self.detach(node);
+            self.len -= 1;
+            self.weight -= (*node.as_ptr()).weight;
 
-        let res = lru.add(1, 1);
-        assert!(res.is_none());
+            let boxed = Box::from_raw(node.as_ptr());
+            self.shrink_if_sparse();
+            self.debug_check();
+            Some((boxed.key.unwrap(), boxed.value.unwrap()))
+        }
+    }
+
+    /// removes the entry at a recency position, walking from the nearer end
+    /// # `Arguments`
+    /// - `index` -> recency position, 0 being the most recently used
+    /// # `Returns`
+    /// - None if `index` is out of range, otherwise the removed (key, value)
+    pub fn remove_index(&mut self, index: usize) -> Option<(K, V)> {
+        if index >= self.len {
+            return None;
+        }
+        unsafe {
+            let node = if index < self.len / 2 {
+                let mut curr = (*self.head.as_ptr()).next.unwrap();
+                for _ in 0..index {
+                    curr = (*curr.as_ptr()).next.unwrap();
+                }
+                curr
+            } else {
+                let mut curr = (*self.tail.as_ptr()).prev.unwrap();
+                for _ in index + 1..self.len {
+                    curr = (*curr.as_ptr()).prev.unwrap();
+                }
+                curr
+            };
+            let entry = self.unlink_entry(node);
+            self.shrink_if_sparse();
+            self.debug_check();
+            Some(entry)
+        }
+    }
+
+    /// removes all entries from the cache, the hashmap keeps its allocation
+    /// for reuse unless auto shrink is enabled
+    pub fn clear(&mut self) {
+        let start = self.start_timer();
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let next = (*curr.as_ptr()).next.unwrap();
+                drop(Box::from_raw(curr.as_ptr()));
+                curr = next;
+            }
+            (*self.head.as_ptr()).next = Some(self.tail);
+            (*self.tail.as_ptr()).prev = Some(self.head);
+        }
+        self.hashmap.clear();
+        self.len = 0;
+        self.weight = 0;
+        self.shrink_if_sparse();
+        self.debug_check();
+        self.record_time(OpKind::Clear, start);
+    }
+
+    /// removes all entries from the cache like `clear` and releases the
+    /// memory of the hashmap, trading reuse for footprint
+    pub fn clear_releasing_capacity(&mut self) {
+        self.clear();
+        self.hashmap.shrink_to_fit();
+        self.debug_check();
+    }
+
+    /// removes all entries and sets a new capacity, the removed entries are
+    /// dropped without going through the eviction callback
+    /// # `Arguments`
+    /// - `new_cap` -> new capacity
+    pub fn clear_and_resize(&mut self, new_cap: usize) {
+        self.clear();
+        self.change_cap(new_cap);
+        self.debug_check();
+    }
+
+    /// keeps only the entries for which `f` returns true, preserving their order
+    /// # `Arguments`
+    /// - `f` -> predicate called with every entry in MRU -> LRU order
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.retain_mut(|key, value| f(key, value));
+    }
+
+    /// keeps only the entries for which `f` returns true like `retain`, `f`
+    /// may mutate the values in place. In the weighted mode call
+    /// `recompute_all_weights` after mutations that change the weight.
+    /// # `Arguments`
+    /// - `f` -> predicate called with every entry in MRU -> LRU order
+    pub fn retain_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let next = (*curr.as_ptr()).next.unwrap();
+                let node = &mut *curr.as_ptr();
+                if !f(node.key.as_ref().unwrap(), node.value.as_mut().unwrap()) {
+                    self.detach(curr);
+                    self.hashmap
+                        .remove(KeyWrapper::from_ref(node.key.as_ref().unwrap()));
+                    self.len -= 1;
+                    self.weight -= node.weight;
+                    drop(Box::from_raw(curr.as_ptr()));
+                }
+                curr = next;
+            }
+        }
+        self.shrink_if_sparse();
+        self.debug_check();
+    }
+
+    /// removes the most recently used entry matching `pred`
+    /// # `Arguments`
+    /// - `pred` -> predicate called with the entries in MRU -> LRU order until
+    ///   one matches
+    /// # `Returns`
+    /// - None if no entry matches, otherwise the removed (key, value)
+    pub fn remove_first_where<F>(&mut self, mut pred: F) -> Option<(K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let node = &*curr.as_ptr();
+                if pred(node.key.as_ref().unwrap(), node.value.as_ref().unwrap()) {
+                    let entry = self.unlink_entry(curr);
+                    self.shrink_if_sparse();
+                    self.debug_check();
+                    return Some(entry);
+                }
+                curr = node.next.unwrap();
+            }
+        }
+        None
+    }
+
+    /// removes every entry matching `pred`, the returning counterpart of
+    /// `retain`
+    /// # `Arguments`
+    /// - `pred` -> predicate called with every entry in MRU -> LRU order
+    /// # `Returns`
+    /// - removed entries in MRU -> LRU order
+    pub fn remove_where<F>(&mut self, mut pred: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut removed = Vec::new();
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let next = (*curr.as_ptr()).next.unwrap();
+                let node = &*curr.as_ptr();
+                if pred(node.key.as_ref().unwrap(), node.value.as_ref().unwrap()) {
+                    removed.push(self.unlink_entry(curr));
+                }
+                curr = next;
+            }
+        }
+        self.shrink_if_sparse();
+        self.debug_check();
+        removed
+    }
+
+    /// reserves room in the hashmap for at least `additional` more entries
+    /// # `Arguments`
+    /// - `additional` -> number of entries to reserve room for
+    pub fn reserve(&mut self, additional: usize) {
+        self.hashmap.reserve(additional);
+    }
+
+    /// reserves room in the hashmap for as many entries as the capacity, so
+    /// filling the cache never rehashes. Like `reserve` it panics if that
+    /// much cannot be allocated, so it suits capacities that fit in memory.
+    pub fn reserve_for_fill(&mut self) {
+        self.hashmap.reserve(self.cap.saturating_sub(self.len));
+    }
+
+    /// reserves room in the hashmap for `additional` more entries without
+    /// over-allocating, for callers who know the final size
+    /// # `Arguments`
+    /// - `additional` -> number of entries to reserve room for
+    pub fn reserve_exact(&mut self, additional: usize) {
+        // `HashMap` has no `reserve_exact`, trim the slack `reserve` may add
+        self.hashmap.reserve(additional);
+        self.hashmap.shrink_to(self.len + additional);
+    }
+
+    /// tries to reserve room in the hashmap for at least `additional` more
+    /// entries, reporting allocation failure instead of aborting
+    /// # `Arguments`
+    /// - `additional` -> number of entries to reserve room for
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.hashmap.try_reserve(additional)
+    }
+
+    /// number of entries the hashmap can hold without reallocating, unlike
+    /// the capacity of the cache this reflects the memory actually allocated
+    pub fn raw_capacity(&self) -> usize {
+        self.hashmap.capacity()
+    }
+
+    /// shrinks the allocation of the hashmap as much as possible while
+    /// keeping room for `min_capacity` entries, the capacity of the cache is
+    /// unaffected
+    /// # `Arguments`
+    /// - `min_capacity` -> lower bound of the hashmap capacity, the length of
+    ///   the cache is used if it is larger
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.hashmap.shrink_to(min_capacity);
+    }
+
+    /// removes every expired entry
+    /// # `Returns`
+    /// - number of entries removed
+    pub fn purge_expired(&mut self) -> usize {
+        self.remove_expired_into(|_, _| {})
+    }
+
+    /// removes every expired entry and hands it to `f`, e.g. to flush it
+    /// # `Arguments`
+    /// - `f` -> called with the expired entries in MRU -> LRU order
+    /// # `Returns`
+    /// - number of entries removed
+    pub fn remove_expired_into<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(K, V),
+    {
+        let now = Instant::now();
+        let mut removed = 0;
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let next = (*curr.as_ptr()).next.unwrap();
+                if (*curr.as_ptr())
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now)
+                {
+                    let (key, value) = self.unlink_entry(curr);
+                    f(key, value);
+                    removed += 1;
+                }
+                curr = next;
+            }
+        }
+        self.shrink_if_sparse();
+        self.debug_check();
+        removed
+    }
+
+    /// evicts from the LRU end until the cache is back within its limits.
+    /// `add` normally keeps it there, but a cache full of pinned entries grows
+    /// past them, and unpinning leaves the overflow for the caller to flush
+    /// on its own schedule.
+    /// # `Returns`
+    /// - evicted entries in LRU -> MRU order, empty when an eviction
+    ///   callback is set as the entries are handed to it in the same order
+    pub fn drain_over_capacity(&mut self) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        self.trim(&mut |key, value| evicted.push((key, value)));
+        self.debug_check();
+        evicted
+    }
+
+    /// removes entries from the LRU end until `stop` returns true for the next
+    /// one, which is kept, or the cache is empty
+    /// # `Arguments`
+    /// - `stop` -> called with each candidate before it is removed
+    /// # `Returns`
+    /// - removed entries in LRU -> MRU order
+    pub fn drain_lru_until<F>(&mut self, mut stop: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut drained = Vec::new();
+        unsafe {
+            loop {
+                let node = (*self.tail.as_ptr()).prev.unwrap();
+                if node == self.head {
+                    break;
+                }
+                let entry = &*node.as_ptr();
+                if stop(entry.key.as_ref().unwrap(), entry.value.as_ref().unwrap()) {
+                    break;
+                }
+                drained.push(self.unlink_entry(node));
+            }
+        }
+        self.shrink_if_sparse();
+        self.debug_check();
+        drained
+    }
+
+    /// removes up to `n` entries from the LRU end, streaming them to `f`
+    /// instead of collecting them. Pinned entries are skipped like on
+    /// eviction.
+    /// # `Arguments`
+    /// - `n` -> maximum number of entries to remove
+    /// - `f` -> called with each removed entry, least recently used first
+    /// # `Returns`
+    /// - number of entries removed
+    pub fn evict_lru_into<F>(&mut self, n: usize, mut f: F) -> usize
+    where
+        F: FnMut(K, V),
+    {
+        let mut removed = 0;
+        while removed < n {
+            let Some((key, value)) = self.pop_lru() else {
+                break;
+            };
+            f(key, value);
+            removed += 1;
+        }
+        self.shrink_if_sparse();
+        self.debug_check();
+        removed
+    }
+
+    /// enables or disables releasing the hashmap allocation after removals
+    /// leave it mostly empty. Reclaiming memory is best-effort.
+    /// # `Arguments`
+    /// - `enabled` -> whether to shrink automatically
+    pub fn set_auto_shrink(&mut self, enabled: bool) {
+        self.auto_shrink = enabled;
+    }
+
+    /// records the time of every insert and promoting access, which
+    /// `iter_accessed_after` filters on. Off by default as reading the clock
+    /// on every hit is not free.
+    /// # `Arguments`
+    /// - `enabled` -> whether to record access times
+    pub fn set_track_access_time(&mut self, enabled: bool) {
+        self.track_access_time = enabled;
+    }
+
+    /// replaces the value of an existing key without promoting it, in the
+    /// weighted mode other entries are evicted if the new value exceeds the
+    /// weight cap. A value that cannot fit even after evicting every other
+    /// unpinned entry is rejected and the entry is left untouched.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// - `value` -> new value of the entry
+    /// # `Returns`
+    /// - None if key not exist or the value was rejected, otherwise the old
+    ///   value
+    pub fn replace_value<Q>(&mut self, key: &Q, value: V) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.hashmap.get(KeyWrapper::from_ref(key))?;
+        let old = unsafe {
+            let weight = self.weigh((*node.as_ptr()).key.as_ref().unwrap(), &value);
+            if weight > self.max_weight || !self.fits_besides_pinned(weight, Some(node)) {
+                return None;
+            }
+            self.reweigh(node, weight, &mut |_, _| {});
+            let old = (*node.as_ptr()).value.replace(value);
+            self.trim_keeping(node);
+            old
+        };
+        self.debug_check();
+        old
+    }
+
+    /// updates the value of an existing key and promotes it, never inserts.
+    /// An expired entry counts as absent. In the weighted mode other entries
+    /// are evicted if the new value exceeds the weight cap, a value that
+    /// cannot fit even after evicting every other unpinned entry is rejected
+    /// and the entry is left untouched.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// - `value` -> new value of the entry
+    /// # `Returns`
+    /// - whether the key was present and updated
+    pub fn set_value_if_present<Q>(&mut self, key: &Q, value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(node) = self.lookup(key) else {
+            return false;
+        };
+        unsafe {
+            let weight = self.weigh((*node.as_ptr()).key.as_ref().unwrap(), &value);
+            if weight > self.max_weight || !self.fits_besides_pinned(weight, Some(node)) {
+                return false;
+            }
+            self.reweigh(node, weight, &mut |_, _| {});
+            (*node.as_ptr()).value = Some(value);
+            self.promote(node);
+            self.trim_keeping(node);
+        }
+        self.debug_check();
+        true
+    }
+
+    /// promotes an existing entry keeping its value, or inserts the new
+    /// value at the MRU end on a miss. Unlike `add` a hit never overwrites
+    /// the stored value. Entries evicted by the insertion go to the eviction
+    /// callback or are dropped.
+    /// # `Arguments`
+    /// - `key` -> key of the entry
+    /// - `value` -> value inserted on a miss, dropped on a hit
+    /// # `Returns`
+    /// - true if the key was present and promoted, false if it was inserted
+    pub fn touch_or_insert(&mut self, key: K, value: V) -> bool {
+        let hit = match self.lookup(&key) {
+            Some(node) => {
+                unsafe { self.promote(node) };
+                true
+            }
+            None => {
+                self.insert_entry(key, value, None, &mut |_, _| {});
+                false
+            }
+        };
+        self.debug_check();
+        hit
+    }
+
+    /// get value associated with the key, awaiting `f` and caching its
+    /// output on a miss.
+    ///
+    /// The cache is borrowed mutably for the whole call, including while the
+    /// future is pending, so it is meant to be driven from a single task.
+    /// Share it between tasks by wrapping it in an async-aware lock.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `f` -> produces the future computing the value on a miss
+    /// # `Returns`
+    /// - value associated with the key
+    pub async fn get_or_insert_with_async<F, Fut>(&mut self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = f().await;
+        self.add(key, value.clone());
+        value
+    }
+
+    /// inserts a new entry at the MRU end, leaving an existing entry for the
+    /// key untouched and unpromoted. Like `add` an entry that can never fit
+    /// is refused, but if every entry is pinned the new one is kept past the
+    /// capacity.
+    /// # `Arguments`
+    /// - `key` -> key of the entry
+    /// - `value` -> value of the entry
+    /// # `Returns`
+    /// - reference to the inserted value, or `TryInsertError::Occupied`
+    ///   holding the rejected entry and the existing value if the key is
+    ///   present, or `TryInsertError::Rejected` holding the entry if the
+    ///   capacity is zero or the entry weighs more than the weight cap
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&V, TryInsertError<'_, K, V>> {
+        if let Some(node) = self.lookup(&key) {
+            return Err(TryInsertError::Occupied(OccupiedError {
+                key,
+                value,
+                existing: unsafe { (*node.as_ptr()).value.as_ref().unwrap() },
+            }));
+        }
+        if self.cap == 0 {
+            let error = LruError::ZeroCapacity;
+            return Err(TryInsertError::Rejected { key, value, error });
+        }
+        if self.weigh(&key, &value) > self.max_weight {
+            let error = LruError::ItemTooLarge;
+            return Err(TryInsertError::Rejected { key, value, error });
+        }
+        let node = self.insert_computed(key, value, &mut |_, _| {});
+        self.debug_check();
+        Ok(unsafe { (*node.as_ptr()).value.as_ref().unwrap() })
+    }
+
+    /// get value associated with the key without promoting it, inserting the
+    /// output of `f` at the MRU end on a miss. The inserted entry is kept
+    /// even if it alone exceeds the limits of the cache.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `f` -> computes the value on a miss
+    /// # `Returns`
+    /// - reference to the value associated with the key
+    pub fn peek_or_insert_with<F>(&mut self, key: K, f: F) -> &V
+    where
+        F: FnOnce() -> V,
+    {
+        let node = match self.lookup(&key) {
+            Some(node) => node,
+            None => self.insert_computed(key, f(), &mut |_, _| {}),
+        };
+        self.debug_check();
+        unsafe { (*node.as_ptr()).value.as_ref().unwrap() }
+    }
+
+    /// get value associated with the key, promoting it, or insert the output
+    /// of `f` at the MRU end on a miss. The inserted entry is kept even if it
+    /// alone exceeds the limits of the cache.
+    ///
+    /// On a miss in a full cache the least recently used unpinned entry is
+    /// evicted. If every entry is pinned nothing can be evicted and the new
+    /// entry is inserted past the capacity, see `try_get_or_insert_with` to
+    /// refuse instead.
+    ///
+    /// `f` must not use the cache. The cache keeps no guard of its own for
+    /// this: it is borrowed mutably for the whole call, so safe code cannot
+    /// reach it from `f` except through a wrapper such as `Rc<RefCell<_>>`,
+    /// which refuses the second borrow before any state is touched. The same
+    /// holds for every `*_or_insert_with*` method.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `f` -> computes the value on a miss
+    /// # `Returns`
+    /// - reference to the value associated with the key
+    pub fn get_or_insert_with<F>(&mut self, key: K, f: F) -> &V
+    where
+        F: FnOnce() -> V,
+    {
+        self.get_or_insert_with_evicted(key, f).0
+    }
+
+    /// same as `get_or_insert_with`, failing instead of growing past the
+    /// limits when the pinned entries leave no room. `f` is only called once
+    /// the entry is known to fit the capacity, in the weighted mode its value
+    /// is then weighed and dropped if it does not fit the weight cap.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `f` -> computes the value on a miss
+    /// # `Returns`
+    /// - reference to the value associated with the key, or on a miss
+    ///   `LruError::ZeroCapacity` if the capacity is zero,
+    ///   `LruError::ItemTooLarge` if the value weighs more than the weight
+    ///   cap and `LruError::AllPinned` if the cache is full of pinned entries
+    ///   or evicting every unpinned entry frees too little weight
+    pub fn try_get_or_insert_with<F>(&mut self, key: K, f: F) -> Result<&V, LruError>
+    where
+        F: FnOnce() -> V,
+    {
+        let start = self.start_timer();
+        let node = match self.lookup(&key) {
+            Some(node) => {
+                unsafe { self.promote(node) };
+                Ok(node)
+            }
+            None if self.cap == 0 => Err(LruError::ZeroCapacity),
+            None if self.len >= self.cap && self.lru_victim().is_none() => Err(LruError::AllPinned),
+            None => {
+                let value = f();
+                let weight = self.weigh(&key, &value);
+                if weight > self.max_weight {
+                    Err(LruError::ItemTooLarge)
+                } else if !self.fits_besides_pinned(weight, None) {
+                    Err(LruError::AllPinned)
+                } else {
+                    Ok(self.insert_computed(key, value, &mut |_, _| {}))
+                }
+            }
+        };
+        self.debug_check();
+        self.record_time(OpKind::Get, start);
+        node.map(|node| unsafe { (*node.as_ptr()).value.as_ref().unwrap() })
+    }
+
+    /// same as `get_or_insert_with`, also handing back what the insertion
+    /// displaced so a write-back cache can flush it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `f` -> computes the value on a miss
+    /// # `Returns`
+    /// - reference to the value associated with the key, and the evicted
+    ///   (key, value) unless an eviction callback took it. If several entries
+    ///   are evicted the least recently used one is returned.
+    pub fn get_or_insert_with_evicted<F>(&mut self, key: K, f: F) -> (&V, Option<(K, V)>)
+    where
+        F: FnOnce() -> V,
+    {
+        let start = self.start_timer();
+        let mut evicted = None;
+        let node = self.get_or_insert_node(key, f, &mut |key, value| {
+            evicted.get_or_insert((key, value));
+        });
+        self.record_time(OpKind::Get, start);
+        (unsafe { (*node.as_ptr()).value.as_ref().unwrap() }, evicted)
+    }
+
+    /// get value associated with the key, promoting it, or insert the output
+    /// of `f` with the given time to live on a miss or when the entry expired
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `ttl` -> time to live of a newly inserted entry
+    /// - `f` -> computes the value on a miss
+    /// # `Returns`
+    /// - reference to the value associated with the key
+    pub fn get_or_insert_with_ttl<F>(&mut self, key: K, ttl: Duration, f: F) -> &V
+    where
+        F: FnOnce() -> V,
+    {
+        let start = self.start_timer();
+        let node = match self.lookup(&key) {
+            Some(node) => {
+                unsafe { self.promote(node) };
+                node
+            }
+            None => {
+                let node = self.insert_computed(key, f(), &mut |_, _| {});
+                unsafe { (*node.as_ptr()).expires_at = Some(Instant::now() + ttl) };
+                node
+            }
+        };
+        self.debug_check();
+        self.record_time(OpKind::Get, start);
+        unsafe { (*node.as_ptr()).value.as_ref().unwrap() }
+    }
+
+    /// get mutable reference to the value associated with the key, promoting
+    /// it, or insert `V::default()` at the MRU end on a miss
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// # `Returns`
+    /// - mutable reference to the value associated with the key
+    pub fn get_or_insert_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        self.get_mut_or_insert_with(key, V::default)
+    }
+
+    /// get mutable reference to the value associated with the key, promoting
+    /// it, or insert the output of `f` at the MRU end on a miss. In the
+    /// weighted mode call `recompute_weight` after a mutation that changes
+    /// the weight.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping
+    /// - `f` -> computes the value on a miss
+    /// # `Returns`
+    /// - mutable reference to the value associated with the key
+    pub fn get_mut_or_insert_with<F>(&mut self, key: K, f: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let start = self.start_timer();
+        let node = self.get_or_insert_node(key, f, &mut |_, _| {});
+        self.record_time(OpKind::Get, start);
+        unsafe { (*node.as_ptr()).value.as_mut().unwrap() }
+    }
+
+    /// finds and promotes the node of the key, or inserts the output of `f`
+    /// on a miss, the untimed core of the `*_or_insert_with` methods
+    fn get_or_insert_node<F>(
+        &mut self,
+        key: K,
+        f: F,
+        sink: &mut dyn FnMut(K, V),
+    ) -> NonNull<Node<K, V>>
+    where
+        F: FnOnce() -> V,
+    {
+        let node = match self.lookup(&key) {
+            Some(node) => {
+                unsafe { self.promote(node) };
+                node
+            }
+            None => self.insert_computed(key, f(), sink),
+        };
+        self.debug_check();
+        node
+    }
+
+    /// inserts an entry for an absent key at the MRU end, evicting from the
+    /// LRU end to make room. The entry is kept even if it alone exceeds the
+    /// limits of the cache.
+    fn insert_computed(
+        &mut self,
+        key: K,
+        value: V,
+        sink: &mut dyn FnMut(K, V),
+    ) -> NonNull<Node<K, V>> {
+        let weight = self.weigh(&key, &value);
+        self.make_room(weight, sink);
+        self.insert_front(key, value, weight)
+    }
+
+    /// promotes every entry matching `pred` to the front, the matches keep
+    /// their relative order among themselves
+    /// # `Arguments`
+    /// - `pred` -> predicate called with every entry in LRU -> MRU order
+    /// # `Returns`
+    /// - number of entries promoted
+    pub fn promote_where<F>(&mut self, mut pred: F) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut promoted = 0;
+        unsafe {
+            // walking from the LRU end, the promoted nodes pile up ahead of
+            // the ones still to visit, so exactly `len` steps see each once
+            let mut curr = (*self.tail.as_ptr()).prev.unwrap();
+            for _ in 0..self.len {
+                let prev = (*curr.as_ptr()).prev.unwrap();
+                let node = &*curr.as_ptr();
+                if pred(node.key.as_ref().unwrap(), node.value.as_ref().unwrap()) {
+                    self.detach(curr);
+                    self.attach_front(curr);
+                    self.touch(curr);
+                    promoted += 1;
+                }
+                curr = prev;
+            }
+        }
+        self.debug_check();
+        promoted
+    }
+
+    /// clones the entries matching `pred` and promotes each of them as it is
+    /// encountered while walking from the MRU to the LRU end. The match that
+    /// was least recently used therefore ends up as the MRU entry, and the
+    /// matches end up at the front in the reverse of their previous order.
+    /// # `Arguments`
+    /// - `pred` -> predicate called with every entry in MRU -> LRU order
+    /// # `Returns`
+    /// - matching entries in their previous MRU -> LRU order
+    pub fn get_all_where<F>(&mut self, mut pred: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut matches = Vec::new();
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let next = (*curr.as_ptr()).next.unwrap();
+                let node = &*curr.as_ptr();
+                let (key, value) = (node.key.as_ref().unwrap(), node.value.as_ref().unwrap());
+                if pred(key, value) {
+                    matches.push((key.clone(), value.clone()));
+                    self.detach(curr);
+                    self.attach_front(curr);
+                    self.touch(curr);
+                }
+                curr = next;
+            }
+        }
+        self.debug_check();
+        matches
+    }
+
+    /// most recently used entry, without promoting it
+    pub fn peek_mru(&self) -> Option<(&K, &V)> {
+        self.iter().next()
+    }
+
+    /// least recently used entry, the next one to be evicted
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        self.iter().next_back()
+    }
+
+    /// entry at a recency position without promoting it
+    /// # `Arguments`
+    /// - `index` -> recency position, 0 being the most recently used
+    /// # `Returns`
+    /// - None if `index` is out of range, otherwise the entry
+    pub fn peek_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.iter().nth(index)
+    }
+
+    /// checks if the key is the least recently used entry, the next one to be
+    /// evicted
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn is_lru<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.lru_key().is_some_and(|lru| lru.borrow() == key)
+    }
+
+    /// checks if the key is the most recently used entry
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn is_mru<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.mru_key().is_some_and(|mru| mru.borrow() == key)
+    }
+
+    /// key of the most recently used entry, does not promote it
+    pub fn mru_key(&self) -> Option<&K> {
+        self.peek_mru().map(|(key, _)| key)
+    }
+
+    /// key of the least recently used entry, the next one to be evicted
+    pub fn lru_key(&self) -> Option<&K> {
+        self.peek_lru().map(|(key, _)| key)
+    }
+
+    /// most recently used entry, same as `peek_mru`
+    pub fn front(&self) -> Option<(&K, &V)> {
+        self.peek_mru()
+    }
+
+    /// least recently used entry, same as `peek_lru`
+    pub fn back(&self) -> Option<(&K, &V)> {
+        self.peek_lru()
+    }
+
+    /// get value associated with the key and move the entry to the LRU end,
+    /// for one-shot reads unlikely to be repeated
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise value associated with the key
+    pub fn peek_and_demote<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.lookup(key)?;
+        unsafe {
+            self.detach(node);
+            self.attach_back(node);
+            self.debug_check();
+            (*node.as_ptr()).value.clone()
+        }
+    }
+
+    /// pins an entry so it is never evicted, a cache full of pinned entries
+    /// grows past its limits on `add`
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - whether the key was found
+    pub fn pin<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.set_pinned(key, true)
+    }
+
+    /// unpins an entry so it can be evicted again
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - whether the key was found
+    pub fn unpin<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.set_pinned(key, false)
+    }
+
+    /// checks if the key exists and is pinned
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    pub fn is_pinned<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hashmap
+            .get(KeyWrapper::from_ref(key))
+            .is_some_and(|node| unsafe { (*node.as_ptr()).pinned })
+    }
+
+    /// moves an existing entry to the LRU end
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - whether the key was found
+    pub fn move_to_back<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(&node) = self.hashmap.get(KeyWrapper::from_ref(key)) else {
+            return false;
+        };
+        unsafe {
+            self.detach(node);
+            self.attach_back(node);
+        }
+        self.debug_check();
+        true
+    }
+
+    /// checks if any entry holds the value, this is a linear scan over all
+    /// entries
+    /// # `Arguments`
+    /// - `value` -> value to look for
+    pub fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        self.iter().any(|(_, v)| v == value)
+    }
+
+    /// counts the entries matching `pred` without promoting them
+    /// # `Arguments`
+    /// - `pred` -> predicate called with every entry in MRU -> LRU order
+    pub fn count_where<F>(&self, mut pred: F) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.iter().filter(|(key, value)| pred(key, value)).count()
+    }
+
+    /// counts the entries per group without promoting them, e.g. to see how
+    /// tenants or shards are spread over the cache
+    /// # `Arguments`
+    /// - `key_fn` -> maps every entry to its group
+    /// # `Returns`
+    /// - number of entries in each group that has any
+    pub fn group_counts<G, F>(&self, mut key_fn: F) -> HashMap<G, usize>
+    where
+        G: Hash + Eq,
+        F: FnMut(&K, &V) -> G,
+    {
+        let mut counts = HashMap::new();
+        for (key, value) in self.iter() {
+            *counts.entry(key_fn(key, value)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// most recently used entry matching `pred`, without promoting it
+    /// # `Arguments`
+    /// - `pred` -> predicate called with the entries in MRU -> LRU order
+    pub fn find<F>(&self, mut pred: F) -> Option<(&K, &V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.iter().find(|(key, value)| pred(key, value))
+    }
+
+    /// iterates over the entries from the most to the least recently used
+    /// without promoting them
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        unsafe {
+            Iter {
+                next: (*self.head.as_ptr()).next.unwrap(),
+                prev: (*self.tail.as_ptr()).prev.unwrap(),
+                len: self.len,
+                marker: PhantomData,
+            }
+        }
+    }
+
+    /// iterates over the entries along with their 0-based recency position,
+    /// 0 being the most recently used, without promoting them
+    pub fn iter_with_recency(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.iter()
+            .enumerate()
+            .map(|(position, (key, value))| (key, value, position))
+    }
+
+    /// counts the entries in equal-width recency bands, a diagnostic of how
+    /// the entries spread over the recency order
+    /// # `Arguments`
+    /// - `buckets` -> number of bands, the first one holds the most recently
+    ///   used entries
+    /// # `Returns`
+    /// - entry count of each band, summing to the length of the cache
+    pub fn recency_histogram(&self, buckets: usize) -> Vec<usize> {
+        let mut histogram = vec![0; buckets];
+        if buckets == 0 {
+            return histogram;
+        }
+        for (_, _, position) in self.iter_with_recency() {
+            let bucket = (position as u128 * buckets as u128 / self.len as u128) as usize;
+            histogram[bucket] += 1;
+        }
+        histogram
+    }
+
+    /// iterates over the entries last accessed after `cutoff` in MRU -> LRU
+    /// order without promoting them. Entries not accessed since
+    /// `set_track_access_time` was enabled have no access time and are
+    /// skipped.
+    /// # `Arguments`
+    /// - `cutoff` -> entries accessed at or before it are skipped
+    pub fn iter_accessed_after(&self, cutoff: Instant) -> impl Iterator<Item = (&K, &V)> {
+        let mut curr = unsafe { (*self.head.as_ptr()).next.unwrap() };
+        std::iter::from_fn(move || unsafe {
+            while curr != self.tail {
+                let node = &*curr.as_ptr();
+                curr = node.next.unwrap();
+                if node
+                    .accessed_at
+                    .is_some_and(|accessed_at| accessed_at > cutoff)
+                {
+                    return Some((node.key.as_ref().unwrap(), node.value.as_ref().unwrap()));
+                }
+            }
+            None
+        })
+    }
+
+    /// iterates over the keys in MRU -> LRU order without promoting them
+    pub fn keys_ordered(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// clones the keys in MRU -> LRU order, so the cache can be mutated while
+    /// walking them. Every key is cloned, which may be costly for large keys.
+    pub fn keys_snapshot(&self) -> Vec<K> {
+        self.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// moves the `n` least recently used entries to the MRU end keeping their
+    /// relative order, rotating the recency ring. Nothing changes if `n` is
+    /// zero or at least `len`.
+    /// # `Arguments`
+    /// - `n` -> number of entries to rotate
+    pub fn rotate_lru_to_mru(&mut self, n: usize) {
+        if n == 0 || n >= self.len {
+            return;
+        }
+        unsafe {
+            let last = (*self.tail.as_ptr()).prev.unwrap();
+            let mut first = last;
+            for _ in 1..n {
+                first = (*first.as_ptr()).prev.unwrap();
+            }
+            let before = (*first.as_ptr()).prev.unwrap();
+            (*before.as_ptr()).next = Some(self.tail);
+            (*self.tail.as_ptr()).prev = Some(before);
+
+            let front = (*self.head.as_ptr()).next.unwrap();
+            (*self.head.as_ptr()).next = Some(first);
+            (*first.as_ptr()).prev = Some(self.head);
+            (*last.as_ptr()).next = Some(front);
+            (*front.as_ptr()).prev = Some(last);
+        }
+        self.debug_check();
+    }
+
+    /// splits the cache in two at the given recency position
+    /// # `Arguments`
+    /// - `at` -> position counted from the MRU end, entries from `at` to the
+    ///   LRU end are moved out
+    /// # `Returns`
+    /// - new cache with the same limits holding the moved entries in order
+    pub fn split_off(&mut self, at: usize) -> LRUCache<K, V> {
+        let mut other = Self::new(self.cap);
+        other.max_weight = self.max_weight;
+        other.weigher = self.weigher.clone();
+        other.track_access_time = self.track_access_time;
+        if at >= self.len {
+            return other;
+        }
+
+        unsafe {
+            let mut first = (*self.head.as_ptr()).next.unwrap();
+            for _ in 0..at {
+                first = (*first.as_ptr()).next.unwrap();
+            }
+            let last = (*self.tail.as_ptr()).prev.unwrap();
+            let before = (*first.as_ptr()).prev.unwrap();
+
+            (*before.as_ptr()).next = Some(self.tail);
+            (*self.tail.as_ptr()).prev = Some(before);
+
+            (*first.as_ptr()).prev = Some(other.head);
+            (*other.head.as_ptr()).next = Some(first);
+            (*last.as_ptr()).next = Some(other.tail);
+            (*other.tail.as_ptr()).prev = Some(last);
+
+            let mut curr = first;
+            while curr != other.tail {
+                let key = (*curr.as_ptr()).key.as_ref().unwrap();
+                let node = self.hashmap.remove(KeyWrapper::from_ref(key)).unwrap();
+                other.hashmap.insert(KeyRef { key }, node);
+                other.weight += (*curr.as_ptr()).weight;
+                curr = (*curr.as_ptr()).next.unwrap();
+            }
+        }
+
+        other.len = self.len - at;
+        self.len = at;
+        self.weight -= other.weight;
+        self.debug_check();
+        other.debug_check();
+        other
+    }
+
+    /// moves an entry from another cache into this one at the MRU end, the
+    /// hit in `from` is reported to its metrics and the expiry carries over.
+    /// An entry this cache evicts to make room goes to its eviction callback
+    /// or is dropped.
+    /// # `Arguments`
+    /// - `from` -> cache to take the entry from
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - whether the entry moved, false if `from` lacks the key or the entry
+    ///   can never fit this cache
+    pub fn steal<Q>(&mut self, from: &mut LRUCache<K, V>, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(node) = from.lookup(key) else {
+            return false;
+        };
+        unsafe {
+            let entry = &*node.as_ptr();
+            let weight = self.weigh(entry.key.as_ref().unwrap(), entry.value.as_ref().unwrap());
+            if self.cap == 0 || weight > self.max_weight {
+                return false;
+            }
+
+            let expires_at = entry.expires_at;
+            let (key, value) = from.unlink_entry(node);
+            from.shrink_if_sparse();
+            from.debug_check();
+            self.add_expiring(key, value, expires_at);
+        }
+        true
+    }
+
+    /// consumes the cache, transforming every value while keeping the keys,
+    /// the recency order, pins, expiries and access times.
+    ///
+    /// The capacity, auto shrink, access time tracking, hit window, metrics,
+    /// timer and resize callback carry over. The weigher and the eviction
+    /// callback take the old value type, so they are dropped together with
+    /// the weight cap and flush on drop: the new cache is unweighted and
+    /// returns its evictions until `set_on_evict` is called on it.
+    /// # `Arguments`
+    /// - `f` -> maps each entry to its new value, called in LRU -> MRU order
+    /// # `Returns`
+    /// - cache holding the transformed values
+    pub fn map_values<W, F>(self, mut f: F) -> LRUCache<K, W>
+    where
+        W: Clone,
+        F: FnMut(&K, V) -> W,
+    {
+        self.filter_map_values(|key, value| Some(f(key, value)))
+    }
+
+    /// consumes the cache like `map_values`, dropping the entries `f` maps to
+    /// `None`. Survivors keep their relative recency order. The same settings
+    /// carry over and the weigher, weight cap, eviction callback and flush
+    /// on drop are dropped, see `map_values`.
+    /// # `Arguments`
+    /// - `f` -> maps each entry to its new value or `None` to drop it, called
+    ///   in LRU -> MRU order
+    /// # `Returns`
+    /// - cache holding the transformed survivors
+    pub fn filter_map_values<W, F>(mut self, mut f: F) -> LRUCache<K, W>
+    where
+        W: Clone,
+        F: FnMut(&K, V) -> Option<W>,
+    {
+        let mut mapped = LRUCache::new(self.cap);
+        mapped.auto_shrink = self.auto_shrink;
+        mapped.track_access_time = self.track_access_time;
+        unsafe {
+            let mut curr = (*self.tail.as_ptr()).prev.unwrap();
+            while curr != self.head {
+                let prev = (*curr.as_ptr()).prev.unwrap();
+                let pinned = (*curr.as_ptr()).pinned;
+                let expires_at = (*curr.as_ptr()).expires_at;
+                let accessed_at = (*curr.as_ptr()).accessed_at;
+                let (key, value) = self.unlink_entry(curr);
+                curr = prev;
+
+                let Some(value) = f(&key, value) else {
+                    continue;
+                };
+                let node = mapped.insert_front(key, value, 0);
+                (*node.as_ptr()).pinned = pinned;
+                (*node.as_ptr()).expires_at = expires_at;
+                (*node.as_ptr()).accessed_at = accessed_at;
+            }
+        }
+        mapped.metrics = self.metrics.take();
+        mapped.timer = self.timer.take();
+        mapped.on_resize = self.on_resize.take();
+        mapped.window = self.window.take();
+        mapped.debug_check();
+        mapped
+    }
+
+    /// reallocates the nodes in recency order and rebuilds the hashmap, so a
+    /// long-lived cache walks memory in order again. Contents and order are
+    /// unchanged.
+    pub fn compact(&mut self) {
+        let mut hashmap = HashMap::with_capacity(self.len);
+        unsafe {
+            let mut prev = self.head;
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                let old = *Box::from_raw(curr.as_ptr());
+                curr = old.next.unwrap();
+
+                let node = Node {
+                    key: old.key,
+                    value: old.value,
+                    weight: old.weight,
+                    pinned: old.pinned,
+                    expires_at: old.expires_at,
+                    accessed_at: old.accessed_at,
+                    prev: Some(prev),
+                    next: None,
+                };
+                let node = NonNull::new_unchecked(Box::into_raw(Box::new(node)));
+                (*prev.as_ptr()).next = Some(node);
+                let key = (*node.as_ptr()).key.as_ref().unwrap();
+                hashmap.insert(KeyRef { key }, node);
+                prev = node;
+            }
+            (*prev.as_ptr()).next = Some(self.tail);
+            (*self.tail.as_ptr()).prev = Some(prev);
+        }
+        self.hashmap = hashmap;
+        self.debug_check();
+    }
+
+    /// walks the list in both directions and checks that the links, the
+    /// sentinels, `len`, the total weight and the hashmap agree
+    /// # `Returns`
+    /// - Err describing the first violation found
+    pub fn check_invariants(&self) -> Result<(), String> {
+        unsafe {
+            let head = &*self.head.as_ptr();
+            let tail = &*self.tail.as_ptr();
+            if head.prev.is_some() || head.key.is_some() || head.value.is_some() {
+                return Err("head sentinel is corrupted".to_string());
+            }
+            if tail.next.is_some() || tail.key.is_some() || tail.value.is_some() {
+                return Err("tail sentinel is corrupted".to_string());
+            }
+
+            let mut forward = Vec::with_capacity(self.len);
+            let mut curr = head.next.ok_or("head sentinel has no next link")?;
+            while curr != self.tail {
+                if forward.len() > self.len {
+                    return Err(format!("forward walk exceeds len {}", self.len));
+                }
+                forward.push(curr);
+                curr = (*curr.as_ptr())
+                    .next
+                    .ok_or("node in the list has no next link")?;
+            }
+
+            let mut backward = Vec::with_capacity(self.len);
+            let mut curr = tail.prev.ok_or("tail sentinel has no prev link")?;
+            while curr != self.head {
+                if backward.len() > self.len {
+                    return Err(format!("backward walk exceeds len {}", self.len));
+                }
+                backward.push(curr);
+                curr = (*curr.as_ptr())
+                    .prev
+                    .ok_or("node in the list has no prev link")?;
+            }
+            backward.reverse();
+
+            if forward.len() != self.len {
+                return Err(format!(
+                    "len is {} but the list holds {} nodes",
+                    self.len,
+                    forward.len()
+                ));
+            }
+            if forward != backward {
+                return Err("forward and backward walks disagree".to_string());
+            }
+            if self.hashmap.len() != self.len {
+                return Err(format!(
+                    "len is {} but the hashmap holds {} keys",
+                    self.len,
+                    self.hashmap.len()
+                ));
+            }
+
+            let mut weight = 0usize;
+            for node in &forward {
+                let key = (*node.as_ptr())
+                    .key
+                    .as_ref()
+                    .ok_or("node in the list has no key")?;
+                if (*node.as_ptr()).value.is_none() {
+                    return Err("node in the list has no value".to_string());
+                }
+                if self.hashmap.get(KeyWrapper::from_ref(key)) != Some(node) {
+                    return Err("hashmap does not point at the node of its key".to_string());
+                }
+                weight = weight
+                    .checked_add((*node.as_ptr()).weight)
+                    .ok_or("the nodes weigh more than usize::MAX")?;
+            }
+            if weight != self.weight {
+                return Err(format!(
+                    "weight is {} but the nodes weigh {}",
+                    self.weight, weight
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// applies a single decoded operation, meant for fuzzers and model-based
+    /// tests comparing the cache against a reference
+    /// # `Arguments`
+    /// - `op` -> operation to apply
+    /// # `Returns`
+    /// - entries produced by the operation: the evicted entries for `Add`
+    ///   and `Resize`, the found entry for `Get`, `Peek`, `PeekMru`,
+    ///   `PeekLru` and `Remove`, nothing for `Clear`
+    pub fn apply_op(&mut self, op: Op<K, V>) -> Vec<(K, V)> {
+        match op {
+            Op::Add(key, value) => self.add(key, value).into_iter().collect(),
+            Op::Get(key) => self
+                .get(&key)
+                .map(|value| (key, value))
+                .into_iter()
+                .collect(),
+            Op::Peek(key) => self
+                .peek(&key)
+                .cloned()
+                .map(|value| (key, value))
+                .into_iter()
+                .collect(),
+            Op::Remove(key) => self.remove_entry(&key).into_iter().collect(),
+            Op::PeekMru => self
+                .peek_mru()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .into_iter()
+                .collect(),
+            Op::PeekLru => self
+                .peek_lru()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .into_iter()
+                .collect(),
+            Op::Clear => {
+                self.clear();
+                Vec::new()
+            }
+            Op::Resize(cap) => self.resize(cap),
+        }
+    }
+
+    /// consumes the cache into its capacity and entries, the counterpart of
+    /// `from_ordered`. The entries are moved out, the sentinels are freed by
+    /// `Drop`.
+    /// # `Returns`
+    /// - capacity and the entries in MRU -> LRU order
+    pub fn into_parts(mut self) -> (usize, Vec<(K, V)>) {
+        let mut entries = Vec::with_capacity(self.len);
+        unsafe {
+            loop {
+                let node = (*self.head.as_ptr()).next.unwrap();
+                if node == self.tail {
+                    break;
+                }
+                entries.push(self.unlink_entry(node));
+            }
+        }
+        (self.cap, entries)
+    }
+
+    /// clones the entries in MRU -> LRU order
+    pub fn to_index_ordered(&self) -> Vec<(K, V)> {
+        self.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// clones the entries into a plain `HashMap`, the recency order is lost
+    pub fn to_hashmap(&self) -> HashMap<K, V> {
+        self.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// references to the up to `n` least recently used entries in LRU ->
+    /// MRU order, without promoting them
+    pub fn lru_n(&self, n: usize) -> Vec<(&K, &V)> {
+        self.iter().rev().take(n).collect()
+    }
+
+    /// references to the up to `n` most recently used entries in MRU ->
+    /// LRU order, without promoting them
+    pub fn mru_n(&self, n: usize) -> Vec<(&K, &V)> {
+        self.iter().take(n).collect()
+    }
+
+    /// references to all entries in MRU -> LRU order, without promoting
+    /// them, materialized in one allocation for indexing and slicing
+    pub fn entries(&self) -> Vec<(&K, &V)> {
+        self.iter().collect()
+    }
+
+    /// reads the clock when a timer is attached
+    fn start_timer(&self) -> Option<Instant> {
+        self.timer.as_ref().map(|_| Instant::now())
+    }
+
+    /// hands the time elapsed since `start` to the timer
+    fn record_time(&self, op: OpKind, start: Option<Instant>) {
+        if let (Some(timer), Some(start)) = (self.timer.as_ref(), start) {
+            timer.record(op, start.elapsed());
+        }
+    }
+
+    /// sets the capacity, notifying the resize callback when it changes
+    fn change_cap(&mut self, new_cap: usize) {
+        let old_cap = std::mem::replace(&mut self.cap, new_cap);
+        if old_cap != new_cap
+            && let Some(on_resize) = self.on_resize.as_mut()
+        {
+            on_resize(old_cap, new_cap);
+        }
+    }
+
+    /// runs `check_invariants` after a mutation when the `debug-checks`
+    /// feature is enabled, panicking on a violation
+    fn debug_check(&self) {
+        #[cfg(feature = "debug-checks")]
+        if let Err(violation) = self.check_invariants() {
+            panic!("LRU cache invariant violated: {violation}");
+        }
+    }
+
+    /// shrinks the hashmap when auto shrink is enabled and less than a
+    /// quarter of its allocation is in use
+    fn shrink_if_sparse(&mut self) {
+        if self.auto_shrink && self.hashmap.capacity() > 4 * self.len.max(8) {
+            self.hashmap.shrink_to(self.len * 2);
+        }
+    }
+
+    /// renames the key of an existing entry, keeping its position
+    /// # `Arguments`
+    /// - `old` -> current key of the entry, may be any borrowed form of the key type
+    /// - `new` -> key to store the entry under
+    /// # `Returns`
+    /// - false if `old` not exist, `new` already exists or the entry would
+    ///   weigh more than the weight cap under `new`, true otherwise
+    pub fn replace_key<Q>(&mut self, old: &Q, new: K) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.hashmap.contains_key(KeyWrapper::from_ref(&new)) {
+            return false;
+        }
+        let Some(&node) = self.hashmap.get(KeyWrapper::from_ref(old)) else {
+            return false;
+        };
+        let weight = self.weigh(&new, unsafe { (*node.as_ptr()).value.as_ref().unwrap() });
+        if weight > self.max_weight {
+            return false;
+        }
+        self.hashmap.remove(KeyWrapper::from_ref(old));
+        unsafe {
+            self.reweigh(node, weight, &mut |_, _| {});
+
+            let entry = &mut *node.as_ptr();
+            entry.key = Some(new);
+            let key = entry.key.as_ref().unwrap();
+            self.hashmap.insert(KeyRef { key }, node);
+        }
+        self.trim(&mut |_, _| {});
+        self.debug_check();
+        true
+    }
+
+    /// swaps the values stored under two keys, keys and positions are kept
+    /// # `Arguments`
+    /// - `a` -> key of the first entry, may be any borrowed form of the key type
+    /// - `b` -> key of the second entry, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - whether both keys exist and both swapped entries fit the weight cap,
+    ///   nothing changes otherwise
+    pub fn swap<Q>(&mut self, a: &Q, b: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (Some(&a), Some(&b)) = (
+            self.hashmap.get(KeyWrapper::from_ref(a)),
+            self.hashmap.get(KeyWrapper::from_ref(b)),
+        ) else {
+            return false;
+        };
+        if a == b {
+            return true;
+        }
+        unsafe {
+            let (entry_a, entry_b) = (&*a.as_ptr(), &*b.as_ptr());
+            let weight_a = self.weigh(
+                entry_a.key.as_ref().unwrap(),
+                entry_b.value.as_ref().unwrap(),
+            );
+            let weight_b = self.weigh(
+                entry_b.key.as_ref().unwrap(),
+                entry_a.value.as_ref().unwrap(),
+            );
+            if weight_a > self.max_weight || weight_b > self.max_weight {
+                return false;
+            }
+            std::mem::swap(&mut (*a.as_ptr()).value, &mut (*b.as_ptr()).value);
+            for (node, other, weight) in [(a, b, weight_a), (b, a, weight_b)] {
+                // the other entry must survive the evictions of the reweigh
+                let pinned = std::mem::replace(&mut (*other.as_ptr()).pinned, true);
+                self.reweigh(node, weight, &mut |_, _| {});
+                (*other.as_ptr()).pinned = pinned;
+            }
+        }
+        self.trim(&mut |_, _| {});
+        self.debug_check();
+        true
+    }
+
+    /// computes the weight of an entry, zero when no weigher is set
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        self.weigher
+            .as_ref()
+            .map_or(0, |weigher| weigher(key, value))
+    }
+
+    /// sets the pinned flag of an entry, returning whether the key was found
+    fn set_pinned<Q>(&mut self, key: &Q, pinned: bool) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(&node) = self.hashmap.get(KeyWrapper::from_ref(key)) else {
+            return false;
+        };
+        unsafe { (*node.as_ptr()).pinned = pinned };
+        self.debug_check();
+        true
+    }
+
+    /// checks if the entry of the node has outlived its time to live
+    unsafe fn is_expired(&self, node: NonNull<Node<K, V>>) -> bool {
+        unsafe {
+            (*node.as_ptr())
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= Instant::now())
+        }
+    }
+
+    /// finds the node of the key, treating an expired entry as absent and
+    /// removing it, and reports the hit or the miss to the metrics
+    fn lookup<Q>(&mut self, key: &Q) -> Option<NonNull<Node<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut node = self.hashmap.get(KeyWrapper::from_ref(key)).copied();
+        if let Some(expired) = node.filter(|&node| unsafe { self.is_expired(node) }) {
+            unsafe { self.unlink_entry(expired) };
+            self.shrink_if_sparse();
+            node = None;
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            match node {
+                Some(_) => metrics.on_hit(),
+                None => metrics.on_miss(),
+            }
+        }
+        if let Some(window) = self.window.as_mut() {
+            window.record(node.is_some());
+        }
+        node
+    }
+
+    /// hands an evicted entry to the eviction callback, or gives it back when
+    /// no callback is set
+    fn evict(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.on_evict();
+        }
+        match self.on_evict.as_mut() {
+            Some(on_evict) => {
+                on_evict(key, value);
+                None
+            }
+            None => Some((key, value)),
+        }
+    }
+
+    /// evicts from the LRU end until both the entry and the weight limits hold
+    fn trim(&mut self, sink: &mut dyn FnMut(K, V)) {
+        while self.len > self.cap || self.weight > self.max_weight {
+            let Some((key, value)) = self.pop_lru() else {
+                break;
+            };
+            if let Some((key, value)) = self.evict(key, value) {
+                sink(key, value);
+            }
+        }
+    }
+
+    /// evicts from the LRU end until an entry of the given weight fits
+    fn make_room(&mut self, weight: usize, sink: &mut dyn FnMut(K, V)) {
+        while self.len >= self.cap
+            || self
+                .weight
+                .checked_add(weight)
+                .is_none_or(|total| total > self.max_weight)
+        {
+            let Some((key, value)) = self.pop_lru() else {
+                break;
+            };
+            if let Some((key, value)) = self.evict(key, value) {
+                sink(key, value);
+            }
+        }
+    }
+
+    /// sets the weight of a linked entry, first evicting other entries from
+    /// the LRU end while the new total would overflow `usize`
+    unsafe fn reweigh(
+        &mut self,
+        node: NonNull<Node<K, V>>,
+        weight: usize,
+        sink: &mut dyn FnMut(K, V),
+    ) {
+        unsafe {
+            let old = (*node.as_ptr()).weight;
+            let pinned = std::mem::replace(&mut (*node.as_ptr()).pinned, true);
+            while (self.weight - old).checked_add(weight).is_none() {
+                let Some((key, value)) = self.pop_lru() else {
+                    break;
+                };
+                if let Some((key, value)) = self.evict(key, value) {
+                    sink(key, value);
+                }
+            }
+            (*node.as_ptr()).pinned = pinned;
+            self.weight = (self.weight - old)
+                .checked_add(weight)
+                .expect("LRU cache weight overflow");
+            (*node.as_ptr()).weight = weight;
+        }
+    }
+
+    /// evicts a single linked entry, e.g. one whose new value can never fit
+    /// the weight cap
+    unsafe fn evict_node(&mut self, node: NonNull<Node<K, V>>) {
+        let (key, value) = unsafe { self.unlink_entry(node) };
+        self.evict(key, value);
+        self.shrink_if_sparse();
+        self.debug_check();
+    }
+
+    /// links a new entry for an absent key at the MRU end
+    fn insert_front(&mut self, key: K, value: V, weight: usize) -> NonNull<Node<K, V>> {
+        let node = Box::into_raw(Box::new(Self::new_node(key, value, weight)));
+        unsafe {
+            let node = NonNull::new_unchecked(node);
+            self.attach_front(node);
+            self.touch(node);
+
+            let key = (*node.as_ptr()).key.as_ref().unwrap();
+            self.hashmap.insert(KeyRef { key }, node);
+            self.len = self.len.checked_add(1).expect("LRU cache length overflow");
+            self.weight = self
+                .weight
+                .checked_add(weight)
+                .expect("LRU cache weight overflow");
+            if let Some(metrics) = self.metrics.as_mut() {
+                metrics.on_insert();
+            }
+            node
+        }
+    }
+
+    /// reuses the node of the eviction victim for a new entry in a full
+    /// cache, saving a free and an allocation on the hottest path. The node
+    /// ends up the same as the one `insert_front` would link.
+    /// # `Returns`
+    /// - the replaced entry
+    fn recycle(&mut self, node: NonNull<Node<K, V>>, key: K, value: V, weight: usize) -> (K, V) {
+        unsafe {
+            let entry = &mut *node.as_ptr();
+            self.hashmap
+                .remove(KeyWrapper::from_ref(entry.key.as_ref().unwrap()));
+            self.detach(node);
+
+            let old = std::mem::replace(entry, Self::new_node(key, value, weight));
+            self.weight = self.weight - old.weight + weight;
+
+            self.attach_front(node);
+            self.touch(node);
+            let key = entry.key.as_ref().unwrap();
+            self.hashmap.insert(KeyRef { key }, node);
+            if let Some(metrics) = self.metrics.as_mut() {
+                metrics.on_insert();
+            }
+            (old.key.unwrap(), old.value.unwrap())
+        }
+    }
+
+    /// unlinked node of a new entry, shared by `insert_front` and `recycle`
+    fn new_node(key: K, value: V, weight: usize) -> Node<K, V> {
+        Node {
+            key: Some(key),
+            value: Some(value),
+            weight,
+            pinned: false,
+            expires_at: None,
+            accessed_at: None,
+            prev: None,
+            next: None,
+        }
+    }
+
+    /// whether an entry of the given weight fits the weight cap once every
+    /// unpinned entry is evicted
+    /// # `Arguments`
+    /// - `weight` -> weight of the entry
+    /// - `replacing` -> entry the weight replaces, neither counted nor evicted
+    fn fits_besides_pinned(&self, weight: usize, replacing: Option<NonNull<Node<K, V>>>) -> bool {
+        let fits = |total: usize| {
+            total
+                .checked_add(weight)
+                .is_some_and(|total| total <= self.max_weight)
+        };
+        let replaced = replacing.map_or(0, |node| unsafe { (*node.as_ptr()).weight });
+        if fits(self.weight - replaced) {
+            return true;
+        }
+        let mut pinned = 0;
+        unsafe {
+            let mut curr = (*self.head.as_ptr()).next.unwrap();
+            while curr != self.tail {
+                if (*curr.as_ptr()).pinned && Some(curr) != replacing {
+                    pinned += (*curr.as_ptr()).weight;
+                }
+                curr = (*curr.as_ptr()).next.unwrap();
+            }
+        }
+        fits(pinned)
+    }
+
+    /// trims the cache back to its limits without evicting the given entry
+    unsafe fn trim_keeping(&mut self, node: NonNull<Node<K, V>>) {
+        unsafe {
+            let pinned = std::mem::replace(&mut (*node.as_ptr()).pinned, true);
+            self.trim(&mut |_, _| {});
+            (*node.as_ptr()).pinned = pinned;
+        }
+    }
+
+    /// least recently used entry that is not pinned
+    fn lru_victim(&self) -> Option<NonNull<Node<K, V>>> {
+        unsafe {
+            let mut curr = (*self.tail.as_ptr()).prev.unwrap();
+            while curr != self.head {
+                if !(*curr.as_ptr()).pinned {
+                    return Some(curr);
+                }
+                curr = (*curr.as_ptr()).prev.unwrap();
+            }
+        }
+        None
+    }
+
+    /// removes the least recently used entry that is not pinned
+    fn pop_lru(&mut self) -> Option<(K, V)> {
+        unsafe {
+            let last_entry = self.lru_victim()?;
+            Some(self.unlink_entry(last_entry))
+        }
+    }
+
+    /// removes the node from the list and the hashmap and frees it
+    /// # `Returns`
+    /// - the entry the node held
+    unsafe fn unlink_entry(&mut self, node: NonNull<Node<K, V>>) -> (K, V) {
+        unsafe {
+            self.detach(node);
+
+            let boxed = Box::from_raw(node.as_ptr());
+            self.hashmap
+                .remove(KeyWrapper::from_ref(boxed.key.as_ref().unwrap()));
+            self.len -= 1;
+            self.weight -= boxed.weight;
+
+            (boxed.key.unwrap(), boxed.value.unwrap())
+        }
+    }
+
+    /// unlinks the node from its neighbours, the node must be in the list
+    unsafe fn detach(&mut self, node: NonNull<Node<K, V>>) {
+        unsafe {
+            let prev = (*node.as_ptr()).prev.unwrap();
+            let next = (*node.as_ptr()).next.unwrap();
+
+            (*prev.as_ptr()).next = Some(next);
+            (*next.as_ptr()).prev = Some(prev);
+        }
+    }
+
+    /// moves the node to the MRU end, leaving the links untouched when it
+    /// already is there
+    unsafe fn promote(&mut self, node: NonNull<Node<K, V>>) {
+        unsafe {
+            self.touch(node);
+            if (*self.head.as_ptr()).next != Some(node) {
+                self.detach(node);
+                self.attach_front(node);
+            }
+        }
+    }
+
+    /// records the access time of the node when access times are tracked
+    unsafe fn touch(&self, node: NonNull<Node<K, V>>) {
+        if self.track_access_time {
+            unsafe { (*node.as_ptr()).accessed_at = Some(Instant::now()) };
+        }
+    }
+
+    /// links the node right before the tail sentinel
+    unsafe fn attach_back(&mut self, node: NonNull<Node<K, V>>) {
+        unsafe {
+            (*node.as_ptr()).next = Some(self.tail);
+            (*node.as_ptr()).prev = (*self.tail.as_ptr()).prev;
+            let tail_prev = (*self.tail.as_ptr()).prev.unwrap();
+
+            (*tail_prev.as_ptr()).next = Some(node);
+            (*self.tail.as_ptr()).prev = Some(node);
+        }
+    }
+
+    /// links the node so that it ends up at recency index `pos`, which must
+    /// not exceed the number of linked nodes
+    unsafe fn attach_at(&mut self, node: NonNull<Node<K, V>>, pos: usize) {
+        unsafe {
+            let mut prev = self.head;
+            for _ in 0..pos {
+                prev = (*prev.as_ptr()).next.unwrap();
+            }
+            let next = (*prev.as_ptr()).next.unwrap();
+
+            (*node.as_ptr()).prev = Some(prev);
+            (*node.as_ptr()).next = Some(next);
+            (*prev.as_ptr()).next = Some(node);
+            (*next.as_ptr()).prev = Some(node);
+        }
+    }
+
+    /// links the node right after the head sentinel
+    unsafe fn attach_front(&mut self, node: NonNull<Node<K, V>>) {
+        unsafe {
+            (*node.as_ptr()).prev = Some(self.head);
+            (*node.as_ptr()).next = (*self.head.as_ptr()).next;
+            let head_next = (*self.head.as_ptr()).next.unwrap();
+
+            (*head_next.as_ptr()).prev = Some(node);
+            (*self.head.as_ptr()).next = Some(node);
+        }
+    }
+
+    /// get first entry of the LRU cache
+    pub fn get_first(&mut self) -> V {
+        unsafe {
+            let next = (*self.head.as_ptr()).next.unwrap();
+
+            let value = (*next.as_ptr()).value.clone();
+
+            value.unwrap()
+        }
+    }
+
+    /// get last entry of the LRU cache
+    pub fn get_last(&mut self) -> V {
+        unsafe {
+            let prev = (*self.tail.as_ptr()).prev.unwrap();
+
+            let value = (*prev.as_ptr()).value.clone();
+
+            value.unwrap()
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> LRUCache<K, Arc<T>> {
+    /// get shared value associated with the key, promoting it. Only the
+    /// `Arc` is cloned, never the value behind it.
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the shared value
+    pub fn get_arc<Q>(&mut self, key: &Q) -> Option<Arc<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_ref(key).map(Arc::clone)
+    }
+
+    /// get shared value associated with the key without promoting it
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise the shared value
+    pub fn peek_arc<Q>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.peek(key).map(Arc::clone)
+    }
+
+    /// non-owning handle to the shared value without promoting it, the
+    /// strong count is untouched so the value still dies with its entry
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise a `Weak` to the value
+    pub fn peek_weak<Q>(&self, key: &Q) -> Option<sync::Weak<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.peek(key).map(Arc::downgrade)
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> LRUCache<K, Rc<T>> {
+    /// non-owning handle to the shared value without promoting it, the
+    /// strong count is untouched so the value still dies with its entry
+    /// # `Arguments`
+    /// - `key` -> key of the mapping, may be any borrowed form of the key type
+    /// # `Returns`
+    /// - None if key not exist, otherwise a `Weak` to the value
+    pub fn peek_weak<Q>(&self, key: &Q) -> Option<rc::Weak<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.peek(key).map(Rc::downgrade)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Extend<(K, V)> for LRUCache<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.add(key, value);
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone + 'a, V: Clone + 'a> Extend<(&'a K, &'a V)> for LRUCache<K, V> {
+    fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.add(key.clone(), value.clone());
+        }
+    }
+}
+
+/// Iterator over the entries of the LRU cache in MRU -> LRU order
+#[derive(Debug)]
+pub struct Iter<'a, K: Hash + Eq + Clone, V: Clone> {
+    next: NonNull<Node<K, V>>,
+    prev: NonNull<Node<K, V>>,
+    len: usize,
+    marker: PhantomData<&'a Node<K, V>>,
+}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        unsafe {
+            let node = &*self.next.as_ptr();
+            self.next = node.next.unwrap();
+            self.len -= 1;
+
+            Some((node.key.as_ref().unwrap(), node.value.as_ref().unwrap()))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        unsafe {
+            let node = &*self.prev.as_ptr();
+            self.prev = node.prev.unwrap();
+            self.len -= 1;
+
+            Some((node.key.as_ref().unwrap(), node.value.as_ref().unwrap()))
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<K: Hash + Eq + Clone, V: Clone> Drop for LRUCache<K, V> {
+    fn drop(&mut self) {
+        // the callback is taken out so it can run while the entries are
+        // unlinked one by one. Each entry leaves the list before the callback
+        // sees it, a panicking callback leaks the rest but frees nothing twice.
+        if self.flush_on_drop
+            && let Some(mut on_evict) = self.on_evict.take()
+        {
+            unsafe {
+                let mut curr = (*self.tail.as_ptr()).prev.unwrap();
+                while curr != self.head {
+                    let prev = (*curr.as_ptr()).prev.unwrap();
+                    let (key, value) = self.unlink_entry(curr);
+                    on_evict(key, value);
+                    curr = prev;
+                }
+            }
+        }
+
+        let mut curr = self.head;
+        unsafe {
+            while curr != self.tail {
+                let next = (*curr.as_ptr()).next.unwrap();
+                drop(Box::from_raw(curr.as_ptr()));
+                curr = next;
+            }
+            drop(Box::from_raw(self.tail.as_ptr()));
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::lru::{
+        AddReport, ArcLRUCache, GetResult, KeyWrapper, LRUCache, LruError, Metrics, OccupiedError,
+        Op, OpKind, Timer, TryInsertError,
+    };
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        pin::pin,
+        rc::Rc,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread, sleep},
+        time::{Duration, Instant},
+    };
+
+    /// wakes the thread running `block_on`
+    struct Unparker(Thread);
+
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// runs a future to completion on the current thread, parking it while
+    /// the future is pending
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn make_lru() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+
+        assert_eq!(lru.get_first(), 2);
+        lru.get(&1);
+        assert_eq!(lru.get_first(), 1);
+
+        lru.get(&2);
+        assert_eq!(lru.get_first(), 2);
+
+        lru.add(3, 3);
+        assert_eq!(lru.get_first(), 3);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(1);
+
+        let res = lru.add(1, 1);
+        assert!(res.is_none());
 
         let res = lru.add(2, 2);
         assert_eq!(Some((1, 1)), res);
 
-        assert_eq!(lru.get_first(), 2);
+        assert_eq!(lru.get_first(), 2);
+
+        let value = lru.get(&1);
+        assert_eq!(None, value);
+
+        let value = lru.get(&2);
+        assert_eq!(Some(2), value);
+    }
+
+    #[test]
+    fn borrowed_key_lookup() {
+        let mut lru: LRUCache<String, u64> = LRUCache::new(3);
+
+        lru.add("one".to_string(), 1);
+        lru.add("two".to_string(), 2);
+
+        assert_eq!(lru.peek("one"), Some(&1));
+        assert!(lru.contains_key("two"));
+        assert!(!lru.contains_key("three"));
+
+        assert_eq!(lru.get("one"), Some(1));
+        assert_eq!(lru.get_first(), 1);
+
+        assert_eq!(lru.remove("one"), Some(1));
+        assert_eq!(lru.remove("one"), None);
+        assert_eq!(lru.get("one"), None);
+        assert_eq!(lru.get_first(), 2);
+    }
+
+    #[test]
+    fn entry_and_weight_limits() {
+        let mut lru: LRUCache<u64, String> =
+            LRUCache::with_limits(2, 10, |_, value: &String| value.len());
+
+        lru.add(1, "a".to_string());
+        lru.add(2, "b".to_string());
+        let res = lru.add(3, "c".to_string());
+        assert_eq!(Some((1, "a".to_string())), res);
+        assert_eq!(lru.weight(), 2);
+
+        let mut lru: LRUCache<u64, String> =
+            LRUCache::with_limits(5, 10, |_, value: &String| value.len());
+
+        lru.add(1, "aaaa".to_string());
+        lru.add(2, "bbbb".to_string());
+        let res = lru.add(3, "cccc".to_string());
+        assert_eq!(Some((1, "aaaa".to_string())), res);
+        assert!(lru.contains_key(&2));
+        assert!(lru.contains_key(&3));
+        assert_eq!(lru.weight(), 8);
+    }
+
+    #[test]
+    fn update_heavier_than_the_cap() {
+        let new = || {
+            let mut lru: LRUCache<u64, Vec<u8>> =
+                LRUCache::with_limits(10, 10, |_, value: &Vec<u8>| value.len());
+            lru.add_many(vec![(1, vec![0; 2]), (2, vec![0; 2]), (3, vec![0; 2])]);
+            lru
+        };
+
+        let mut lru = new();
+        assert_eq!(lru.add(3, vec![0; 50]), Some((3, vec![0; 50])));
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+        assert_eq!(lru.peek(&3), Some(&vec![0; 2]));
+        assert_eq!(lru.weight(), 6);
+
+        let mut lru = new();
+        assert_eq!(lru.replace_value(&3, vec![0; 50]), None);
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+        assert_eq!(lru.peek(&3), Some(&vec![0; 2]));
+        assert_eq!(lru.weight(), 6);
+
+        let mut lru = new();
+        assert!(!lru.set_value_if_present(&1, vec![0; 50]));
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+        assert_eq!(lru.peek(&1), Some(&vec![0; 2]));
+        assert_eq!(lru.weight(), 6);
+
+        let mut lru = new();
+        lru.peek_mut(&2).unwrap().resize(50, 0);
+        assert!(lru.recompute_weight(&2));
+        assert_eq!(lru.keys_snapshot(), vec![3, 1]);
+        assert_eq!(lru.weight(), 4);
+        lru.check_invariants().unwrap();
+
+        let mut lru: LRUCache<u64, u64> =
+            LRUCache::with_limits(10, 10, |key, value| (*key * *value) as usize);
+        lru.add(1, 6);
+        lru.add(2, 1);
+        assert!(!lru.swap(&1, &2));
+        assert!(!lru.replace_key(&1, 3));
+        assert!(!lru.replace_key(&2, 20));
+        assert_eq!(lru.to_index_ordered(), vec![(2, 1), (1, 6)]);
+        assert_eq!(lru.weight(), 8);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn replace_value_keeps_order() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+
+        assert_eq!(lru.replace_value(&1, 10), Some(1));
+        assert_eq!(lru.replace_value(&3, 3), None);
+        assert_eq!(lru.get_first(), 2);
+        assert_eq!(lru.get_last(), 10);
+        assert!(!lru.contains_key(&3));
+    }
+
+    #[test]
+    fn replace_value_keeps_the_updated_entry() {
+        let mut lru: LRUCache<u64, Vec<u8>> =
+            LRUCache::with_limits(10, 10, |_, value: &Vec<u8>| value.len());
+        lru.add_many(vec![(1, vec![0; 3]), (2, vec![0; 3]), (3, vec![0; 3])]);
+
+        // 1 is the LRU entry, the others make room for its new value
+        assert_eq!(lru.replace_value(&1, vec![0; 6]), Some(vec![0; 3]));
+        assert_eq!(lru.keys_snapshot(), vec![3, 1]);
+        assert_eq!(lru.weight(), 9);
+
+        // with 3 pinned the new value can never fit, nothing is written
+        lru.pin(&3);
+        assert_eq!(lru.replace_value(&1, vec![0; 8]), None);
+        assert_eq!(lru.peek(&1), Some(&vec![0; 6]));
+        assert_eq!(lru.keys_snapshot(), vec![3, 1]);
+        assert_eq!(lru.weight(), 9);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn get_or_insert_with_async() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        let value = block_on(lru.get_or_insert_with_async(1, || async { 10 }));
+        assert_eq!(value, 10);
+        assert_eq!(lru.peek(&1), Some(&10));
+
+        let value = block_on(lru.get_or_insert_with_async(1, || async { 20 }));
+        assert_eq!(value, 10);
+
+        // the cache stays borrowed across a real suspension point
+        let pending = Cell::new(0);
+        let value = block_on(lru.get_or_insert_with_async(2, || {
+            std::future::poll_fn(|cx| {
+                if pending.get() > 0 {
+                    return Poll::Ready(20);
+                }
+                pending.set(pending.get() + 1);
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+        }));
+        assert_eq!(value, 20);
+        assert_eq!(pending.get(), 1);
+        assert_eq!(lru.keys_snapshot(), vec![2, 1]);
+    }
+
+    #[test]
+    fn keys_snapshot_allows_mutation() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i * 10);
+        }
+        assert_eq!(lru.keys_snapshot(), vec![5, 4, 3, 2, 1]);
+
+        for key in lru.keys_snapshot() {
+            if key % 2 == 0 {
+                lru.remove(&key);
+            }
+        }
+        assert_eq!(lru.keys_snapshot(), vec![5, 3, 1]);
+        assert_eq!(lru.peek(&3), Some(&30));
+    }
+
+    #[test]
+    fn split_off() {
+        let make = || {
+            let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+            for i in 1..=5 {
+                lru.add(i, i);
+            }
+            lru
+        };
+
+        let mut lru = make();
+        let other = lru.split_off(0);
+        assert!(lru.keys_snapshot().is_empty());
+        assert_eq!(other.keys_snapshot(), vec![5, 4, 3, 2, 1]);
+
+        let mut lru = make();
+        let other = lru.split_off(5);
+        assert_eq!(lru.keys_snapshot(), vec![5, 4, 3, 2, 1]);
+        assert!(other.keys_snapshot().is_empty());
+
+        let mut lru = make();
+        let mut other = lru.split_off(2);
+        assert_eq!(lru.keys_snapshot(), vec![5, 4]);
+        assert_eq!(other.keys_snapshot(), vec![3, 2, 1]);
+        assert!(!lru.contains_key(&3));
+        assert_eq!(other.get(&1), Some(1));
+        assert_eq!(other.get_first(), 1);
+
+        lru.add(6, 6);
+        assert_eq!(lru.keys_snapshot(), vec![6, 5, 4]);
+    }
+
+    #[test]
+    fn add_updates_existing_key() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+        assert_eq!(lru.add(1, 10), None);
+
+        assert_eq!(lru.keys_snapshot(), vec![1, 2]);
+        assert_eq!(lru.peek(&1), Some(&10));
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn invariants_hold_under_random_operations() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::with_limits(8, 40, |_, value| *value as usize);
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..5000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let key = state % 16;
+            match (state >> 8) % 5 {
+                0 | 1 => {
+                    lru.add(key, key);
+                }
+                2 => {
+                    lru.get(&key);
+                }
+                3 => {
+                    lru.remove(&key);
+                }
+                _ => {
+                    lru.replace_value(&key, (state >> 16) % 10);
+                }
+            }
+            lru.check_invariants().unwrap();
+        }
+    }
+
+    #[test]
+    fn lru_n() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+
+        for i in 1..=4 {
+            lru.add(i, i * 10);
+        }
+        lru.get(&1);
+
+        assert_eq!(lru.lru_n(2), vec![(&2, &20), (&3, &30)]);
+        assert_eq!(
+            lru.lru_n(10),
+            vec![(&2, &20), (&3, &30), (&4, &40), (&1, &10)]
+        );
+        assert_eq!(lru.keys_snapshot(), vec![1, 4, 3, 2]);
+    }
+
+    #[test]
+    fn mru_n() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+
+        for i in 1..=4 {
+            lru.add(i, i * 10);
+        }
+        lru.get(&2);
+
+        assert_eq!(lru.mru_n(2), vec![(&2, &20), (&4, &40)]);
+        assert!(lru.mru_n(0).is_empty());
+    }
+
+    #[test]
+    fn clear_and_retain() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        lru.retain(|_, value| value % 2 == 1);
+        assert_eq!(lru.keys_snapshot(), vec![5, 3, 1]);
+        assert!(lru.check_invariants().is_ok());
+
+        lru.clear();
+        assert!(lru.keys_snapshot().is_empty());
+        assert!(lru.check_invariants().is_ok());
+
+        lru.add(6, 6);
+        assert_eq!(lru.get_first(), 6);
+    }
+
+    #[test]
+    fn auto_shrink() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(1000);
+        lru.set_auto_shrink(true);
+
+        for i in 0..1000 {
+            lru.add(i, i);
+        }
+        lru.retain(|key, _| *key < 10);
+        for i in 10..20 {
+            assert_eq!(lru.remove(&i), None);
+        }
+        for i in 0..5 {
+            assert_eq!(lru.remove(&i), Some(i));
+        }
+        assert_eq!(lru.keys_snapshot(), vec![9, 8, 7, 6, 5]);
+        assert!(lru.check_invariants().is_ok());
+
+        lru.clear();
+        lru.add(1, 1);
+        assert_eq!(lru.get(&1), Some(1));
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn take_keeps_order_of_others() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+
+        for i in 1..=4 {
+            lru.add(i, i * 10);
+        }
+        assert_eq!(lru.take(&3), Some(30));
+        assert_eq!(lru.take(&3), None);
+        assert_eq!(lru.keys_snapshot(), vec![4, 2, 1]);
+        assert_eq!(lru.remove_entry(&2), Some((2, 20)));
+        assert_eq!(lru.keys_snapshot(), vec![4, 1]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn from_ordered() {
+        let entries = vec![(1, 10), (2, 20), (3, 30)];
+        let lru: LRUCache<u64, u64> = LRUCache::from_ordered(5, entries.clone());
+        assert_eq!(
+            lru.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            entries
+        );
+
+        let lru: LRUCache<u64, u64> = LRUCache::from_ordered(2, entries);
+        assert_eq!(
+            lru.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            vec![(1, 10), (2, 20)]
+        );
+    }
+
+    #[test]
+    fn peek_or_insert_with() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+
+        assert_eq!(*lru.peek_or_insert_with(1, || 10), 1);
+        assert_eq!(lru.get_first(), 2);
+
+        assert_eq!(*lru.peek_or_insert_with(3, || 3), 3);
+        assert_eq!(lru.keys_snapshot(), vec![3, 2]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn eviction_callback_sees_lru_first() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        lru.set_on_evict(move |key, _| sink.borrow_mut().push(key));
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        lru.get(&2);
+        assert_eq!(lru.add(6, 6), None);
+        assert_eq!(*seen.borrow(), vec![1]);
+
+        assert!(lru.resize(2).is_empty());
+        assert_eq!(*seen.borrow(), vec![1, 3, 4, 5]);
+        assert_eq!(lru.keys_snapshot(), vec![6, 2]);
+    }
+
+    #[test]
+    fn resize_returns_evicted_without_callback() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.resize(1), vec![(1, 1), (2, 2)]);
+        assert!(lru.resize(4).is_empty());
+        lru.add(4, 4);
+        assert_eq!(lru.keys_snapshot(), vec![4, 3]);
+    }
+
+    #[test]
+    fn get_all_where_promotes_matches() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        let matches = lru.get_all_where(|_, value| value % 2 == 0);
+        assert_eq!(matches, vec![(4, 4), (2, 2)]);
+        assert_eq!(lru.keys_snapshot(), vec![2, 4, 5, 3, 1]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn extreme_capacity() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(usize::MAX);
+
+        for i in 0..10 {
+            assert_eq!(lru.add(i, i), None);
+        }
+        assert_eq!(lru.get(&0), Some(0));
+        assert!(lru.check_invariants().is_ok());
+
+        let mut lru: LRUCache<u64, usize> =
+            LRUCache::with_limits(usize::MAX, usize::MAX, |_, value| *value);
+        lru.add(1, usize::MAX - 1);
+        lru.add(2, 1);
+        assert_eq!(lru.weight(), usize::MAX);
+        assert_eq!(lru.add(3, 1), Some((1, usize::MAX - 1)));
+        assert_eq!(lru.weight(), 2);
+        assert!(lru.contains_key(&2));
+        assert!(lru.check_invariants().is_ok());
+
+        assert_eq!(lru.remove(&1), None);
+        assert_eq!(lru.remove(&2), Some(1));
+        assert_eq!(lru.remove(&3), Some(1));
+        assert_eq!(lru.weight(), 0);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn get_ref() {
+        let mut lru: LRUCache<u64, Vec<u8>> = LRUCache::new(3);
+
+        lru.add(1, vec![1]);
+        lru.add(2, vec![2]);
+        lru.add(3, vec![3]);
+
+        assert_eq!(lru.get_ref(&1), Some(&vec![1]));
+        assert_eq!(lru.get_ref(&1), Some(&vec![1]));
+        assert_eq!(lru.get_ref(&4), None);
+        assert_eq!(lru.keys_snapshot(), vec![1, 3, 2]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn clear_and_resize() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        lru.clear_and_resize(2);
+        assert!(lru.keys_snapshot().is_empty());
+
+        assert_eq!(lru.add(6, 6), None);
+        assert_eq!(lru.add(7, 7), None);
+        assert_eq!(lru.add(8, 8), Some((6, 6)));
+        assert_eq!(lru.keys_snapshot(), vec![8, 7]);
+    }
+
+    #[test]
+    fn move_to_back() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        assert_eq!(lru.peek_lru(), None);
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.peek_mru(), Some((&3, &3)));
+        assert!(lru.move_to_back(&3));
+        assert!(!lru.move_to_back(&4));
+        assert_eq!(lru.peek_lru(), Some((&3, &3)));
+        assert_eq!(lru.peek_mru(), Some((&2, &2)));
+        assert!(lru.check_invariants().is_ok());
+
+        assert_eq!(lru.add(4, 4), Some((3, 3)));
+    }
+
+    #[test]
+    fn count_where() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.count_where(|_, value| value % 2 == 0), 2);
+        assert_eq!(lru.count_where(|_, _| false), 0);
+        assert_eq!(lru.get_first(), 5);
+    }
+
+    #[derive(Debug, PartialEq, Eq, Hash)]
+    struct CountedKey(u64);
+
+    static KEY_CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    impl Clone for CountedKey {
+        fn clone(&self) -> Self {
+            KEY_CLONES.fetch_add(1, Ordering::SeqCst);
+            CountedKey(self.0)
+        }
+    }
+
+    #[test]
+    fn keys_are_stored_once() {
+        let mut lru: LRUCache<CountedKey, u64> = LRUCache::new(2);
+
+        lru.add(CountedKey(1), 1);
+        lru.add(CountedKey(2), 2);
+        lru.add(CountedKey(1), 10);
+        assert_eq!(lru.get(&CountedKey(2)), Some(2));
+        assert_eq!(lru.add(CountedKey(3), 3), Some((CountedKey(1), 10)));
+        assert_eq!(lru.remove(&CountedKey(2)), Some(2));
+        let other = lru.split_off(0);
+        assert!(other.contains_key(&CountedKey(3)));
+        assert!(other.check_invariants().is_ok());
+
+        assert_eq!(KEY_CLONES.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn clear_on_evict() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(1);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        lru.set_on_evict(move |key, _| sink.borrow_mut().push(key));
+
+        lru.add(1, 1);
+        assert_eq!(lru.add(2, 2), None);
+        assert_eq!(*seen.borrow(), vec![1]);
+
+        lru.clear_on_evict();
+        assert_eq!(lru.add(3, 3), Some((2, 2)));
+        assert_eq!(*seen.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn front_and_back() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        assert_eq!(lru.front(), None);
+        assert_eq!(lru.back(), None);
+
+        for i in 1..=3 {
+            lru.add(i, i * 10);
+        }
+        lru.get(&1);
+        assert_eq!(lru.front(), Some((&1, &10)));
+        assert_eq!(lru.back(), Some((&2, &20)));
+        assert_eq!(lru.front(), lru.peek_mru());
+        assert_eq!(lru.back(), lru.peek_lru());
+    }
+
+    #[test]
+    fn try_reserve() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(16);
+
+        assert!(lru.try_reserve(16).is_ok());
+        for i in 0..16 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.get(&0), Some(0));
+        assert!(lru.try_reserve(usize::MAX).is_err());
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn get_if() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.get_if(&1, |value| *value > 5), Some(1));
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+
+        assert_eq!(lru.get_if(&1, |value| *value < 5), Some(1));
+        assert_eq!(lru.keys_snapshot(), vec![1, 3, 2]);
+
+        assert_eq!(lru.get_if(&4, |_| true), None);
+    }
+
+    #[test]
+    fn get_or_insert_default() {
+        let mut lru: LRUCache<u64, Vec<u32>> = LRUCache::new(2);
+
+        lru.get_or_insert_default(1).push(1);
+        lru.add(2, vec![2]);
+        lru.get_or_insert_default(1).push(2);
+
+        assert_eq!(lru.peek(&1), Some(&vec![1, 2]));
+        assert_eq!(lru.keys_snapshot(), vec![1, 2]);
+    }
+
+    #[test]
+    fn extend_from_references() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        let map: HashMap<u64, u64> = (1..=3).map(|i| (i, i * 10)).collect();
+
+        lru.extend(map.iter());
+        for (key, value) in &map {
+            assert_eq!(lru.peek(key), Some(value));
+        }
+
+        lru.extend([(4, 40), (5, 50)]);
+        assert_eq!(lru.keys_snapshot().len(), 4);
+        assert_eq!(lru.peek_mru(), Some((&5, &50)));
+    }
+
+    #[test]
+    fn full_cache_recycles_lru_node() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        lru.get(&1);
+        assert_eq!(lru.add(4, 4), Some((2, 2)));
+        assert_eq!(lru.add(5, 5), Some((3, 3)));
+        assert_eq!(lru.keys_snapshot(), vec![5, 4, 1]);
+        assert_eq!(lru.peek(&4), Some(&4));
+        assert!(!lru.contains_key(&2));
+        assert!(lru.check_invariants().is_ok());
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::with_limits(2, 10, |_, value| *value as usize);
+        lru.add(1, 3);
+        lru.add(2, 3);
+        assert_eq!(lru.add(3, 9), Some((1, 3)));
+        assert_eq!(lru.keys_snapshot(), vec![3]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i * 10);
+        }
+        assert_eq!(lru.to_index_ordered(), vec![(3, 30), (2, 20), (1, 10)]);
+
+        let map = lru.to_hashmap();
+        assert_eq!(map.len(), 3);
+        for i in 1..=3 {
+            assert_eq!(map.get(&i), Some(&(i * 10)));
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Counts {
+        hits: usize,
+        misses: usize,
+        inserts: usize,
+        evictions: usize,
+    }
+
+    struct CountingMetrics(Rc<RefCell<Counts>>);
+
+    impl Metrics for CountingMetrics {
+        fn on_hit(&mut self) {
+            self.0.borrow_mut().hits += 1;
+        }
+
+        fn on_miss(&mut self) {
+            self.0.borrow_mut().misses += 1;
+        }
+
+        fn on_insert(&mut self) {
+            self.0.borrow_mut().inserts += 1;
+        }
+
+        fn on_evict(&mut self) {
+            self.0.borrow_mut().evictions += 1;
+        }
+    }
+
+    #[test]
+    fn metrics_hooks() {
+        let counts = Rc::new(RefCell::new(Counts::default()));
+        let mut lru: LRUCache<u64, u64> =
+            LRUCache::new(2).with_metrics(CountingMetrics(counts.clone()));
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+        lru.add(1, 10);
+        lru.get(&1);
+        lru.get(&3);
+        lru.add(3, 3);
+        lru.add(4, 4);
+        lru.peek(&4);
+
+        let counts = counts.borrow();
+        assert_eq!(counts.hits, 1);
+        assert_eq!(counts.misses, 1);
+        assert_eq!(counts.inserts, 4);
+        assert_eq!(counts.evictions, 2);
+    }
+
+    #[test]
+    fn find() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.find(|_, value| *value == 3), Some((&3, &3)));
+        assert_eq!(lru.find(|_, value| value % 2 == 0), Some((&4, &4)));
+        lru.get(&2);
+        assert_eq!(lru.find(|_, value| value % 2 == 0), Some((&2, &2)));
+        assert_eq!(lru.find(|_, value| *value > 5), None);
+        assert_eq!(lru.keys_snapshot(), vec![2, 5, 4, 3, 1]);
+    }
+
+    #[test]
+    fn replace_key() {
+        let mut lru: LRUCache<String, u64> = LRUCache::new(3);
+
+        lru.add("a".to_string(), 1);
+        lru.add("b".to_string(), 2);
+        lru.add("c".to_string(), 3);
+
+        assert!(lru.replace_key("b", "B".to_string()));
+        assert_eq!(lru.keys_snapshot(), vec!["c", "B", "a"]);
+        assert_eq!(lru.peek("B"), Some(&2));
+        assert!(!lru.contains_key("b"));
+
+        assert!(!lru.replace_key("x", "y".to_string()));
+        assert!(!lru.replace_key("a", "c".to_string()));
+        assert_eq!(lru.peek("a"), Some(&1));
+        assert_eq!(lru.peek("c"), Some(&3));
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn swap() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i * 10);
+        }
+        assert!(lru.swap(&1, &3));
+        assert_eq!(lru.peek(&1), Some(&30));
+        assert_eq!(lru.peek(&3), Some(&10));
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+
+        assert!(!lru.swap(&1, &4));
+        assert_eq!(lru.peek(&1), Some(&30));
+        assert!(lru.swap(&2, &2));
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn get_and_remove_if() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.get_and_remove_if(&1, |value| *value > 1), None);
+        assert_eq!(lru.keys_snapshot(), vec![1, 3, 2]);
+
+        assert_eq!(lru.get_and_remove_if(&2, |value| *value > 1), Some(2));
+        assert_eq!(lru.keys_snapshot(), vec![1, 3]);
+        assert_eq!(lru.get_and_remove_if(&2, |_| true), None);
+    }
+
+    #[test]
+    fn grow() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        lru.add(1, 1);
+        lru.add(2, 2);
+        lru.grow(1);
+        assert_eq!(lru.add(3, 3), None);
+        assert_eq!(lru.add(4, 4), Some((1, 1)));
+
+        lru.grow(usize::MAX);
+        assert_eq!(lru.add(5, 5), None);
+    }
+
+    #[test]
+    fn pinned_entries_are_not_evicted() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        assert!(lru.pin(&1));
+        assert!(!lru.pin(&4));
+        assert!(lru.is_pinned(&1));
+
+        assert_eq!(lru.add(4, 4), Some((2, 2)));
+        assert_eq!(lru.resize(2), vec![(3, 3)]);
+        assert_eq!(lru.keys_snapshot(), vec![4, 1]);
+
+        assert!(lru.pin(&4));
+        assert_eq!(lru.add(5, 5), None);
+        assert_eq!(lru.keys_snapshot(), vec![5, 4, 1]);
+
+        assert!(lru.unpin(&1));
+        assert!(!lru.is_pinned(&1));
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn error_variants() {
+        assert_eq!(
+            LRUCache::<u64, u64>::try_new(0).unwrap_err(),
+            LruError::ZeroCapacity
+        );
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(0);
+        assert_eq!(lru.try_add(1, 1), Err(LruError::ZeroCapacity));
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::with_limits(2, 10, |_, value| *value as usize);
+        assert_eq!(lru.try_add(1, 11), Err(LruError::ItemTooLarge));
+        assert!(!lru.contains_key(&1));
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::try_new(2).unwrap();
+        assert_eq!(lru.try_add(1, 1), Ok(None));
+        assert_eq!(lru.try_add(2, 2), Ok(None));
+        lru.pin(&1);
+        lru.pin(&2);
+        assert_eq!(lru.try_add(3, 3), Err(LruError::AllPinned));
+        assert_eq!(lru.try_add(1, 10), Ok(None));
+        assert_eq!(lru.keys_snapshot(), vec![1, 2]);
+    }
+
+    #[test]
+    fn iter_with_recency() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i * 10);
+        }
+        lru.get(&1);
+        let triples: Vec<_> = lru.iter_with_recency().collect();
+        assert_eq!(triples, vec![(&1, &10, 0), (&3, &30, 1), (&2, &20, 2)]);
+    }
+
+    #[test]
+    fn compact() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(10);
+
+        for i in 0..10 {
+            lru.add(i, i);
+        }
+        for i in (0..10).step_by(3) {
+            lru.remove(&i);
+        }
+        lru.get(&4);
+        lru.pin(&5);
+        let before = lru.to_index_ordered();
+
+        lru.compact();
+        assert_eq!(lru.to_index_ordered(), before);
+        assert!(lru.is_pinned(&5));
+        assert!(lru.check_invariants().is_ok());
+
+        lru.add(10, 10);
+        assert_eq!(lru.get(&1), Some(1));
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn set_capacity_shrink_paths() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        for i in 1..=4 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.set_capacity(2), vec![(1, 1), (2, 2)]);
+        assert_eq!(lru.keys_snapshot(), vec![4, 3]);
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        lru.set_on_evict(move |key, value| sink.borrow_mut().push((key, value)));
+        for i in 1..=4 {
+            lru.add(i, i);
+        }
+        assert!(lru.set_capacity(1).is_empty());
+        assert_eq!(*seen.borrow(), vec![(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(lru.keys_snapshot(), vec![4]);
+    }
+
+    #[test]
+    fn contains_value() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i * 10);
+        }
+        assert!(lru.contains_value(&20));
+        assert!(!lru.contains_value(&2));
+    }
+
+    #[test]
+    fn ttl_expiry() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        lru.add_with_ttl(1, 1, Duration::from_millis(20));
+        lru.add_with_ttl(2, 2, Duration::from_secs(60));
+        assert_eq!(lru.peek(&1), Some(&1));
+
+        sleep(Duration::from_millis(30));
+        assert_eq!(lru.peek(&1), None);
+        assert!(!lru.contains_key(&1));
+        assert_eq!(lru.get(&1), None);
+        assert_eq!(lru.keys_snapshot(), vec![2]);
+
+        lru.add_with_ttl(2, 20, Duration::ZERO);
+        lru.add(2, 2);
+        assert_eq!(lru.get(&2), Some(2));
+    }
+
+    #[test]
+    fn get_or_insert_with_ttl() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+        let calls = Cell::new(0);
+        let compute = |value| {
+            calls.set(calls.get() + 1);
+            value
+        };
+
+        assert_eq!(
+            *lru.get_or_insert_with_ttl(1, Duration::from_secs(60), || compute(1)),
+            1
+        );
+        assert_eq!(calls.get(), 1);
+
+        assert_eq!(
+            *lru.get_or_insert_with_ttl(1, Duration::from_secs(60), || compute(10)),
+            1
+        );
+        assert_eq!(calls.get(), 1);
+
+        lru.add_with_ttl(2, 2, Duration::from_millis(10));
+        sleep(Duration::from_millis(20));
+        assert_eq!(
+            *lru.get_or_insert_with_ttl(2, Duration::from_secs(60), || compute(20)),
+            20
+        );
+        assert_eq!(calls.get(), 2);
+        assert_eq!(lru.keys_snapshot(), vec![2, 1]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn remove_expired_into() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        lru.add_with_ttl(1, 1, Duration::from_millis(10));
+        lru.add(2, 2);
+        lru.add_with_ttl(3, 3, Duration::from_millis(10));
+        lru.add_with_ttl(4, 4, Duration::from_secs(60));
+        sleep(Duration::from_millis(20));
+
+        let mut flushed = Vec::new();
+        assert_eq!(
+            lru.remove_expired_into(|key, value| flushed.push((key, value))),
+            2
+        );
+        assert_eq!(flushed, vec![(3, 3), (1, 1)]);
+        assert_eq!(lru.keys_snapshot(), vec![4, 2]);
+        assert_eq!(lru.purge_expired(), 0);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn reserve_exact() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(100);
+
+        lru.reserve_exact(100);
+        for i in 0..150 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.get(&50), Some(50));
+        assert!(!lru.contains_key(&49));
+        assert!(lru.check_invariants().is_ok());
+
+        lru.reserve(10);
+        lru.add(150, 150);
+        assert_eq!(lru.peek_mru(), Some((&150, &150)));
+    }
+
+    #[test]
+    fn drain_lru_until() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        assert_eq!(
+            lru.drain_lru_until(|_, value| *value == 3),
+            vec![(1, 1), (2, 2)]
+        );
+        assert_eq!(lru.keys_snapshot(), vec![5, 4, 3]);
+
+        assert_eq!(lru.drain_lru_until(|_, _| false).len(), 3);
+        assert!(lru.keys_snapshot().is_empty());
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn arc_values_are_shared() {
+        let mut lru: ArcLRUCache<u64, Vec<u8>> = LRUCache::new(2);
+
+        lru.add(1, Arc::new(vec![0; 1024]));
+        let a = lru.get_arc(&1).unwrap();
+        let b = lru.peek_arc(&1).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(Arc::strong_count(&a), 3);
+
+        assert!(lru.get_arc(&2).is_none());
+        lru.remove(&1);
+        assert_eq!(Arc::strong_count(&a), 2);
+    }
+
+    #[test]
+    fn rotate_lru_to_mru() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+
+        for i in 1..=5 {
+            lru.add(i, i);
+        }
+        lru.rotate_lru_to_mru(2);
+        assert_eq!(lru.keys_snapshot(), vec![2, 1, 5, 4, 3]);
+        assert!(lru.check_invariants().is_ok());
+
+        lru.rotate_lru_to_mru(5);
+        lru.rotate_lru_to_mru(0);
+        assert_eq!(lru.keys_snapshot(), vec![2, 1, 5, 4, 3]);
+    }
+
+    #[test]
+    fn try_get_outcomes() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        lru.add(1, 1);
+        lru.add_with_ttl(2, 2, Duration::from_millis(10));
+        sleep(Duration::from_millis(20));
+
+        assert_eq!(lru.try_get(&1), GetResult::Hit(1));
+        assert_eq!(lru.try_get(&2), GetResult::Expired);
+        assert_eq!(lru.try_get(&2), GetResult::Miss);
+        assert_eq!(lru.try_get(&3), GetResult::Miss);
+        assert_eq!(lru.keys_snapshot(), vec![1]);
+    }
+
+    #[test]
+    fn add_many_reports_all_evictions() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        lru.add(1, 1);
+        let evicted = lru.add_many(vec![(2, 2), (3, 3), (4, 4), (2, 20)]);
+        assert_eq!(evicted, vec![(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(lru.keys_snapshot(), vec![2, 4]);
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::with_limits(5, 10, |_, value| *value as usize);
+        lru.add_many(vec![(1, 3), (2, 3), (3, 3)]);
+        let evicted = lru.add_many(vec![(4, 9)]);
+        assert_eq!(evicted, vec![(1, 3), (2, 3), (3, 3)]);
+        assert!(lru.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn keys_ordered() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        lru.get(&2);
+        lru.get(&1);
+        assert_eq!(lru.keys_ordered().collect::<Vec<_>>(), vec![&1, &2, &3]);
+        assert_eq!(lru.keys_ordered().collect::<Vec<_>>(), vec![&1, &2, &3]);
+    }
+
+    #[test]
+    fn peek_and_demote() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+
+        for i in 1..=3 {
+            lru.add(i, i);
+        }
+        assert_eq!(lru.peek_and_demote(&3), Some(3));
+        assert_eq!(lru.peek_lru(), Some((&3, &3)));
+        assert_eq!(lru.peek_and_demote(&4), None);
+        assert_eq!(lru.add(4, 4), Some((3, 3)));
+    }
+
+    #[cfg(feature = "debug-checks")]
+    #[test]
+    fn debug_checks_pass_for_all_operations() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::with_limits(6, 60, |_, value| *value as usize);
+
+        lru.add_many((0..10).map(|i| (i, i)).collect());
+        lru.add_with_ttl(20, 1, Duration::ZERO);
+        lru.get(&8);
+        lru.try_get(&20);
+        lru.get_if(&7, |_| true);
+        lru.get_and_remove_if(&6, |_| false);
+        lru.peek_and_demote(&9);
+        lru.move_to_back(&8);
+        lru.replace_value(&7, 9);
+        lru.peek_mut(&9);
+        lru.pin(&9);
+        lru.unpin(&9);
+        lru.replace_key(&7, 17);
+        lru.swap(&17, &8);
+        lru.peek_or_insert_with(30, || 3);
+        lru.get_or_insert_default(31);
+        lru.get_or_insert_with_ttl(32, Duration::from_secs(60), || 2);
+        lru.get_all_where(|key, _| key % 2 == 0);
+        lru.rotate_lru_to_mru(2);
+        lru.compact();
+        let mut other = lru.split_off(3);
+        other.retain(|_, value| *value > 1);
+        other.drain_lru_until(|_, _| false);
+        lru.remove(&30);
+        lru.purge_expired();
+        lru.set_capacity(2);
+        lru.grow(1);
+        lru.clear_and_resize(4);
+        lru.add(1, 1);
+        lru.clear_releasing_capacity();
+        lru.add(2, 2);
+        lru.clear();
+    }
+
+    /// reference model, entries kept in MRU -> LRU order
+    struct Model {
+        cap: usize,
+        entries: Vec<(u8, u32)>,
+    }
+
+    impl Model {
+        fn apply(&mut self, op: Op<u8, u32>) -> Vec<(u8, u32)> {
+            let position =
+                |entries: &Vec<(u8, u32)>, key: u8| entries.iter().position(|(k, _)| *k == key);
+            match op {
+                Op::Add(key, value) => {
+                    if let Some(i) = position(&self.entries, key) {
+                        self.entries.remove(i);
+                        self.entries.insert(0, (key, value));
+                        return Vec::new();
+                    }
+                    if self.cap == 0 {
+                        return vec![(key, value)];
+                    }
+                    let evicted = if self.entries.len() == self.cap {
+                        self.entries.pop()
+                    } else {
+                        None
+                    };
+                    self.entries.insert(0, (key, value));
+                    evicted.into_iter().collect()
+                }
+                Op::Get(key) => match position(&self.entries, key) {
+                    Some(i) => {
+                        let entry = self.entries.remove(i);
+                        self.entries.insert(0, entry);
+                        vec![entry]
+                    }
+                    None => Vec::new(),
+                },
+                Op::Peek(key) => position(&self.entries, key)
+                    .map(|i| self.entries[i])
+                    .into_iter()
+                    .collect(),
+                Op::Remove(key) => position(&self.entries, key)
+                    .map(|i| self.entries.remove(i))
+                    .into_iter()
+                    .collect(),
+                Op::PeekMru => self.entries.first().copied().into_iter().collect(),
+                Op::PeekLru => self.entries.last().copied().into_iter().collect(),
+                Op::Clear => {
+                    self.entries.clear();
+                    Vec::new()
+                }
+                Op::Resize(cap) => {
+                    self.cap = cap;
+                    let keep = self.entries.len().min(cap);
+                    self.entries.drain(keep..).rev().collect()
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn apply_op_matches_model() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut lru: LRUCache<u8, u32> = LRUCache::new(4);
+        let mut model = Model {
+            cap: 4,
+            entries: Vec::new(),
+        };
+        for step in 0..20_000 {
+            let key = (next() % 12) as u8;
+            let op = match next() % 100 {
+                0..=39 => Op::Add(key, step),
+                40..=64 => Op::Get(key),
+                65..=79 => Op::Peek(key),
+                80..=89 => Op::Remove(key),
+                90..=93 => Op::PeekMru,
+                94..=97 => Op::PeekLru,
+                98 => Op::Clear,
+                _ => Op::Resize((next() % 8) as usize),
+            };
+
+            assert_eq!(lru.apply_op(op.clone()), model.apply(op.clone()), "{op:?}");
+            assert_eq!(lru.len, model.entries.len());
+            assert_eq!(lru.to_index_ordered(), model.entries);
+            lru.check_invariants().unwrap();
+        }
+    }
+
+    #[test]
+    fn get_or_falls_back_without_inserting() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+        lru.add(1, 10);
+        lru.add(2, 20);
+
+        assert_eq!(lru.get_or(&1, 0), 10);
+        assert_eq!(lru.peek_mru(), Some((&1, &10)));
+
+        assert_eq!(lru.get_or(&3, 30), 30);
+        assert!(!lru.contains_key(&3));
+        assert_eq!(lru.len, 2);
+    }
+
+    #[test]
+    fn add_at_position_front_back_and_middle() {
+        let keys = |lru: &LRUCache<u64, u64>| lru.keys_ordered().copied().collect::<Vec<_>>();
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        lru.add_many(vec![(1, 1), (2, 2), (3, 3)]);
+
+        assert_eq!(lru.add_at_position(4, 4, 0), None);
+        assert_eq!(keys(&lru), vec![4, 3, 2, 1]);
 
-        let value = lru.get(1);
-        assert_eq!(None, value);
+        assert_eq!(lru.add_at_position(5, 5, 2), None);
+        assert_eq!(keys(&lru), vec![4, 3, 5, 2, 1]);
 
-        let value = lru.get(2);
-        assert_eq!(Some(2), value);
+        // full cache evicts from the tail before the new entry lands last
+        assert_eq!(lru.add_at_position(6, 6, 99), Some((1, 1)));
+        assert_eq!(keys(&lru), vec![4, 3, 5, 2, 6]);
+
+        // an existing key is replaced and moved
+        assert_eq!(lru.add_at_position(4, 40, 4), None);
+        assert_eq!(keys(&lru), vec![3, 5, 2, 6, 4]);
+        assert_eq!(lru.peek(&4), Some(&40));
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn add_at_position_keeps_the_pin() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+        lru.add_many(vec![(1, 1), (2, 2)]);
+        lru.pin(&1);
+
+        assert_eq!(lru.add_at_position(1, 10, 1), None);
+        assert!(lru.is_pinned(&1));
+        assert_eq!(lru.keys_snapshot(), vec![2, 1]);
+
+        // the pinned LRU entry is skipped, 2 goes instead
+        assert_eq!(lru.add(3, 3), Some((2, 2)));
+        assert_eq!(lru.keys_snapshot(), vec![3, 1]);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn mru_and_lru_keys() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+        assert_eq!(lru.mru_key(), None);
+        assert_eq!(lru.lru_key(), None);
+
+        lru.add_many(vec![(1, 10), (2, 20), (3, 30)]);
+        assert_eq!(lru.mru_key(), Some(&3));
+        assert_eq!(lru.lru_key(), Some(&1));
+
+        // reading the keys does not promote
+        assert_eq!(lru.lru_key(), Some(&1));
+        assert_eq!(lru.add(4, 40), Some((1, 10)));
+    }
+
+    #[test]
+    fn map_values_keeps_order_and_capacity() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        lru.add_many(vec![(1, 10), (2, 20), (3, 30)]);
+        lru.get(&1);
+
+        let mut mapped: LRUCache<u64, String> =
+            lru.map_values(|key, value| format!("{key}:{value}"));
+        assert_eq!(
+            mapped.to_index_ordered(),
+            vec![
+                (1, "1:10".to_string()),
+                (3, "3:30".to_string()),
+                (2, "2:20".to_string()),
+            ]
+        );
+        assert_eq!(mapped.cap, 4);
+        mapped.check_invariants().unwrap();
+
+        mapped.add(4, "4".to_string());
+        assert_eq!(
+            mapped.add(5, "5".to_string()),
+            Some((2, "2:20".to_string()))
+        );
+    }
+
+    #[test]
+    fn filter_map_values_drops_and_keeps_order() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        lru.add_many((1..=5).map(|i| (i, i)).collect());
+        lru.get(&2);
+
+        let mut mapped = lru.filter_map_values(|_, value| (value % 2 == 0).then_some(value * 2));
+        assert_eq!(mapped.to_index_ordered(), vec![(2, 4), (4, 8)]);
+        assert_eq!(mapped.cap, 5);
+        mapped.check_invariants().unwrap();
+        assert_eq!(mapped.get(&3), None);
+    }
+
+    #[test]
+    fn map_values_drops_the_weigher() {
+        let mut lru: LRUCache<u64, Vec<u8>> =
+            LRUCache::with_limits(3, 10, |_, value: &Vec<u8>| value.len());
+        lru.set_window(4);
+        lru.add_many(vec![(1, vec![0; 4]), (2, vec![0; 4])]);
+        lru.get(&1);
+        assert_eq!(lru.weight(), 8);
+
+        let mut mapped = lru.map_values(|_, value| value.repeat(4));
+        assert_eq!(mapped.weight(), 0);
+        assert_eq!(mapped.max_weight, usize::MAX);
+        assert_eq!(mapped.recent_hit_ratio(), 1.0);
+        assert_eq!(mapped.add(3, vec![0; 16]), None);
+        assert_eq!(mapped.add(4, vec![0; 16]), Some((2, vec![0; 16])));
+        mapped.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn recency_histogram_sums_to_len() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(10);
+        assert_eq!(lru.recency_histogram(3), vec![0, 0, 0]);
+
+        lru.add_many((0..7).map(|i| (i, i)).collect());
+        let histogram = lru.recency_histogram(3);
+        assert_eq!(histogram, vec![3, 2, 2]);
+        assert_eq!(histogram.iter().sum::<usize>(), lru.len);
+
+        assert_eq!(lru.recency_histogram(10).iter().sum::<usize>(), lru.len);
+        assert_eq!(lru.recency_histogram(1), vec![7]);
+        assert!(lru.recency_histogram(0).is_empty());
+    }
+
+    #[test]
+    fn flush_on_drop_hands_entries_to_the_callback() {
+        let flushed = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+            let sink = Rc::clone(&flushed);
+            lru.set_on_evict(move |key, _| sink.borrow_mut().push(key));
+            lru.set_flush_on_drop(true);
+            lru.add_many(vec![(1, 1), (2, 2), (3, 3), (4, 4)]);
+            lru.get(&2);
+            lru.pin(&3);
+        }
+        assert_eq!(*flushed.borrow(), vec![1, 3, 4, 2]);
+
+        flushed.borrow_mut().clear();
+        {
+            let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+            let sink = Rc::clone(&flushed);
+            lru.set_on_evict(move |key, _| sink.borrow_mut().push(key));
+            lru.add(1, 1);
+        }
+        assert!(flushed.borrow().is_empty());
+    }
+
+    #[test]
+    fn steal_moves_an_entry_between_caches() {
+        let mut l1: LRUCache<u64, u64> = LRUCache::new(2);
+        let mut l2: LRUCache<u64, u64> = LRUCache::new(4);
+        l1.add_many(vec![(1, 1), (2, 2)]);
+        l2.add_many(vec![(3, 3), (4, 4)]);
+
+        assert!(l1.steal(&mut l2, &3));
+        assert!(!l2.contains_key(&3));
+        assert_eq!(l2.len, 1);
+        assert_eq!(l1.peek_mru(), Some((&3, &3)));
+        // l1 made room by evicting its LRU entry
+        assert!(!l1.contains_key(&1));
+
+        assert!(!l1.steal(&mut l2, &3));
+        let mut empty: LRUCache<u64, u64> = LRUCache::new(0);
+        assert!(!empty.steal(&mut l2, &4));
+        assert!(l2.contains_key(&4));
+        l1.check_invariants().unwrap();
+        l2.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn try_insert_rejects_present_keys() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        assert_eq!(lru.try_insert(1, 10), Ok(&10));
+        assert_eq!(lru.try_insert(2, 20), Ok(&20));
+
+        let err = lru.try_insert(1, 11).unwrap_err();
+        assert_eq!(
+            err,
+            TryInsertError::Occupied(OccupiedError {
+                key: 1,
+                value: 11,
+                existing: &10,
+            })
+        );
+        // the existing entry is neither changed nor promoted
+        assert_eq!(lru.peek(&1), Some(&10));
+        assert_eq!(lru.try_insert(3, 30), Ok(&30));
+        assert!(!lru.contains_key(&1));
+    }
+
+    #[test]
+    fn try_insert_rejects_zero_capacity() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(0);
+
+        assert_eq!(
+            lru.try_insert(1, 10),
+            Err(TryInsertError::Rejected {
+                key: 1,
+                value: 10,
+                error: LruError::ZeroCapacity,
+            })
+        );
+        assert_eq!(lru.len, 0);
+        assert_eq!(lru.add(2, 20), Some((2, 20)));
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn try_insert_rejects_entries_heavier_than_the_cap() {
+        let mut lru: LRUCache<u64, Vec<u8>> =
+            LRUCache::with_limits(4, 10, |_, value: &Vec<u8>| value.len());
+        lru.add(1, vec![0; 4]);
+
+        assert_eq!(
+            lru.try_insert(2, vec![0; 11]),
+            Err(TryInsertError::Rejected {
+                key: 2,
+                value: vec![0; 11],
+                error: LruError::ItemTooLarge,
+            })
+        );
+        assert_eq!(lru.keys_snapshot(), vec![1]);
+        assert_eq!(lru.weight(), 4);
+        assert_eq!(lru.try_insert(2, vec![0; 10]), Ok(&vec![0; 10]));
+        assert_eq!(lru.keys_snapshot(), vec![2]);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn peek_index_walks_the_recency_order() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        lru.add_many(vec![(1, 10), (2, 20), (3, 30)]);
+
+        assert_eq!(lru.peek_index(0), Some((&3, &30)));
+        assert_eq!(lru.peek_index(1), Some((&2, &20)));
+        assert_eq!(lru.peek_index(lru.len - 1), Some((&1, &10)));
+        assert_eq!(lru.peek_index(lru.len), None);
+
+        // peeking the LRU entry leaves it next in line for eviction
+        lru.add(4, 40);
+        assert_eq!(lru.add(5, 50), Some((1, 10)));
+    }
+
+    #[test]
+    fn remove_index_front_back_and_middle() {
+        let keys = |lru: &LRUCache<u64, u64>| lru.keys_ordered().copied().collect::<Vec<_>>();
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(6);
+        lru.add_many((1..=6).map(|i| (i, i)).collect());
+
+        assert_eq!(lru.remove_index(0), Some((6, 6)));
+        assert_eq!(keys(&lru), vec![5, 4, 3, 2, 1]);
+
+        assert_eq!(lru.remove_index(lru.len - 1), Some((1, 1)));
+        assert_eq!(keys(&lru), vec![5, 4, 3, 2]);
+
+        assert_eq!(lru.remove_index(1), Some((4, 4)));
+        assert_eq!(lru.remove_index(2), Some((2, 2)));
+        assert_eq!(keys(&lru), vec![5, 3]);
+
+        assert_eq!(lru.remove_index(2), None);
+        assert!(!lru.contains_key(&4));
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn is_lru_and_is_mru() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+        assert!(!lru.is_lru(&1));
+        assert!(!lru.is_mru(&1));
+
+        lru.add_many(vec![(1, 1), (2, 2), (3, 3)]);
+        assert!(lru.is_lru(&1));
+        assert!(lru.is_mru(&3));
+
+        lru.get(&1);
+        assert!(lru.is_mru(&1));
+        assert!(!lru.is_lru(&1));
+        assert!(lru.is_lru(&2));
+        assert!(!lru.is_mru(&4));
+    }
+
+    #[test]
+    fn group_counts_by_parity() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(10);
+        assert!(lru.group_counts(|_, value| value % 2).is_empty());
+
+        lru.add_many((1..=7).map(|i| (i, i * 3)).collect());
+        let counts = lru.group_counts(|_, value| value % 2 == 0);
+        assert_eq!(counts, HashMap::from([(true, 3), (false, 4)]));
+    }
+
+    #[test]
+    fn add_report_describes_the_insertion() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        let fresh = AddReport {
+            inserted: true,
+            old_value: None,
+            evicted: vec![],
+        };
+        assert_eq!(lru.add_report(1, 10), fresh);
+        assert_eq!(lru.add_report(2, 20), fresh);
+
+        assert_eq!(
+            lru.add_report(1, 11),
+            AddReport {
+                inserted: false,
+                old_value: Some(10),
+                evicted: vec![],
+            }
+        );
+        assert_eq!(
+            lru.add_report(3, 30),
+            AddReport {
+                inserted: true,
+                old_value: None,
+                evicted: vec![(2, 20)],
+            }
+        );
+
+        let mut empty: LRUCache<u64, u64> = LRUCache::new(0);
+        assert_eq!(
+            empty.add_report(1, 10),
+            AddReport {
+                inserted: false,
+                old_value: None,
+                evicted: vec![(1, 10)],
+            }
+        );
+    }
+
+    #[test]
+    fn get_or_insert_with_reports_the_eviction() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+
+        assert_eq!(lru.get_or_insert_with(1, || 10), &10);
+        assert_eq!(lru.get_or_insert_with_evicted(2, || 20), (&20, None));
+        assert_eq!(lru.get_or_insert_with_evicted(1, || 0), (&10, None));
+
+        // 2 became the LRU entry when 1 was promoted
+        assert_eq!(
+            lru.get_or_insert_with_evicted(3, || 30),
+            (&30, Some((2, 20)))
+        );
+        assert_eq!(lru.keys_snapshot(), vec![3, 1]);
+    }
+
+    #[test]
+    fn iter_accessed_after_cutoff() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        lru.add(1, 1);
+        lru.set_track_access_time(true);
+        lru.add_many(vec![(2, 2), (3, 3), (4, 4)]);
+        lru.get(&2);
+
+        sleep(Duration::from_millis(5));
+        let cutoff = Instant::now();
+        sleep(Duration::from_millis(5));
+        lru.get(&3);
+        lru.add(5, 5);
+        lru.peek(&4);
+
+        let recent: Vec<_> = lru.iter_accessed_after(cutoff).collect();
+        assert_eq!(recent, vec![(&5, &5), (&3, &3)]);
+        assert_eq!(lru.iter_accessed_after(Instant::now()).count(), 0);
+    }
+
+    #[test]
+    fn set_value_if_present_updates_only() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+        lru.add_many(vec![(1, 10), (2, 20), (3, 30)]);
+
+        assert!(lru.set_value_if_present(&1, 11));
+        assert_eq!(lru.peek_mru(), Some((&1, &11)));
+        assert_eq!(lru.add(4, 40), Some((2, 20)));
+
+        assert!(!lru.set_value_if_present(&2, 22));
+        assert!(!lru.contains_key(&2));
+        assert_eq!(lru.len, 3);
+    }
+
+    #[test]
+    fn recycled_node_matches_a_fresh_one() {
+        let metadata = |lru: &LRUCache<u64, u64>, key: u64| unsafe {
+            let node = &*lru.hashmap[KeyWrapper::from_ref(&key)].as_ptr();
+            (node.weight, node.pinned, node.expires_at, node.accessed_at)
+        };
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(1);
+        lru.set_track_access_time(true);
+        lru.add_with_ttl(1, 1, Duration::from_secs(60));
+        assert!(metadata(&lru, 1).3.is_some());
+        lru.set_track_access_time(false);
+        assert_eq!(lru.add(2, 2), Some((1, 1)));
+
+        let mut fresh: LRUCache<u64, u64> = LRUCache::new(1);
+        fresh.add(2, 2);
+        assert_eq!(metadata(&lru, 2), metadata(&fresh, 2));
+        assert_eq!(metadata(&lru, 2), (0, false, None, None));
+    }
+
+    #[test]
+    fn set_value_if_present_keeps_the_updated_entry() {
+        let mut lru: LRUCache<u64, Vec<u8>> =
+            LRUCache::with_limits(10, 10, |_, value: &Vec<u8>| value.len());
+        lru.add_many(vec![(1, vec![0; 3]), (2, vec![0; 3]), (3, vec![0; 3])]);
+        lru.pin(&2);
+        lru.pin(&3);
+
+        // the pinned 6 leave room for 4 at most, nothing is written
+        assert!(!lru.set_value_if_present(&1, vec![0; 5]));
+        assert_eq!(lru.peek(&1), Some(&vec![0; 3]));
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+        assert_eq!(lru.weight(), 9);
+
+        assert!(lru.set_value_if_present(&1, vec![0; 4]));
+        assert_eq!(lru.keys_snapshot(), vec![1, 3, 2]);
+        assert_eq!(lru.weight(), 10);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn raw_capacity_covers_len() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(100);
+        assert_eq!(lru.raw_capacity(), 0);
+
+        lru.add_many((0..40).map(|i| (i, i)).collect());
+        assert!(lru.raw_capacity() >= lru.len);
+
+        lru.reserve(100);
+        assert!(lru.raw_capacity() >= lru.len + 100);
+    }
+
+    #[test]
+    fn clear_keeping_and_releasing_capacity() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(100);
+        lru.add_many((0..50).map(|i| (i, i)).collect());
+        let raw_capacity = lru.raw_capacity();
+
+        lru.clear();
+        assert_eq!(lru.len, 0);
+        assert_eq!(lru.raw_capacity(), raw_capacity);
+        lru.add(1, 1);
+        assert_eq!(lru.get(&1), Some(1));
+
+        lru.add_many((0..50).map(|i| (i, i)).collect());
+        lru.clear_releasing_capacity();
+        assert_eq!(lru.len, 0);
+        assert_eq!(lru.raw_capacity(), 0);
+        assert_eq!(lru.peek_mru(), None);
+        lru.add(2, 2);
+        assert_eq!(lru.get(&2), Some(2));
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn get_or_insert_with_moves_the_key() {
+        let mut lru: LRUCache<CountedKey, u64> = LRUCache::new(2);
+
+        assert_eq!(lru.get_or_insert_with(CountedKey(1), || 1), &1);
+        assert_eq!(lru.get_or_insert_with(CountedKey(2), || 2), &2);
+        assert_eq!(lru.get_or_insert_with(CountedKey(1), || 0), &1);
+        let (value, evicted) = lru.get_or_insert_with_evicted(CountedKey(3), || 3);
+        assert_eq!((value, evicted), (&3, Some((CountedKey(2), 2))));
+
+        // KEY_CLONES is shared with keys_are_stored_once, neither test clones
+        assert_eq!(KEY_CLONES.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn peek_weak_does_not_keep_values_alive() {
+        let mut lru: ArcLRUCache<u64, String> = LRUCache::new(1);
+        lru.add(1, Arc::new("one".to_string()));
+
+        let weak = lru.peek_weak(&1).unwrap();
+        assert_eq!(weak.strong_count(), 1);
+        assert_eq!(weak.upgrade().as_deref(), Some(&"one".to_string()));
+        assert!(lru.peek_weak(&2).is_none());
+
+        lru.add(2, Arc::new("two".to_string()));
+        assert!(weak.upgrade().is_none());
+
+        let mut lru: LRUCache<u64, Rc<u64>> = LRUCache::new(1);
+        lru.add(1, Rc::new(1));
+        let weak = lru.peek_weak(&1).unwrap();
+        lru.remove(&1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn recompute_weight_after_mutation() {
+        let mut lru: LRUCache<u64, Vec<u8>> =
+            LRUCache::with_limits(10, 10, |_, value: &Vec<u8>| value.len());
+        lru.add_many(vec![(1, vec![0; 3]), (2, vec![0; 3]), (3, vec![0; 3])]);
+        assert_eq!(lru.weight(), 9);
+
+        lru.get_mut(&3).unwrap().extend([0; 3]);
+        assert_eq!(lru.weight(), 9);
+        assert!(lru.recompute_weight(&3));
+        // 12 exceeds the cap of 10, so the LRU entry goes
+        assert_eq!(lru.weight(), 9);
+        assert!(!lru.contains_key(&1));
+        assert!(!lru.recompute_weight(&1));
+
+        lru.peek_mut(&2).unwrap().push(0);
+        lru.peek_mut(&3).unwrap().clear();
+        lru.recompute_all_weights();
+        assert_eq!(lru.weight(), 4);
+        assert_eq!(lru.keys_snapshot(), vec![3, 2]);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn with_capacity_presizes_the_map() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::with_capacity(100);
+        let raw_capacity = lru.raw_capacity();
+        assert!(raw_capacity >= 100);
+
+        lru.add_many((0..100).map(|i| (i, i)).collect());
+        assert_eq!(lru.raw_capacity(), raw_capacity);
+        assert_eq!(lru.get(&0), Some(0));
+        assert_eq!(lru.add(100, 100), Some((1, 1)));
+    }
+
+    #[test]
+    fn get_mut_or_insert_with_appends() {
+        let mut lru: LRUCache<&str, Vec<u64>> = LRUCache::new(2);
+
+        lru.get_mut_or_insert_with("a", Vec::new).push(1);
+        lru.add("b", vec![]);
+        lru.get_mut_or_insert_with("a", || unreachable!()).push(2);
+
+        assert_eq!(lru.peek_mru(), Some((&"a", &vec![1, 2])));
+        assert_eq!(lru.len, 2);
+    }
+
+    #[test]
+    fn remove_first_where_takes_the_mru_match() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        lru.add_many((1..=5).map(|i| (i, i)).collect());
+        lru.get(&2);
+
+        assert_eq!(
+            lru.remove_first_where(|_, value| value % 2 == 0),
+            Some((2, 2))
+        );
+        assert_eq!(
+            lru.remove_first_where(|_, value| value % 2 == 0),
+            Some((4, 4))
+        );
+        assert_eq!(lru.remove_first_where(|_, value| value % 2 == 0), None);
+        assert_eq!(lru.keys_snapshot(), vec![5, 3, 1]);
+    }
+
+    #[test]
+    fn retain_mut_increments_and_filters() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        lru.add_many((1..=5).map(|i| (i, i)).collect());
+
+        lru.retain_mut(|_, value| {
+            *value += 1;
+            *value <= 4
+        });
+        assert_eq!(lru.to_index_ordered(), vec![(3, 4), (2, 3), (1, 2)]);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn on_resize_reports_capacity_changes() {
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&changes);
+        let mut lru: LRUCache<u64, u64> =
+            LRUCache::new(4).on_resize(move |old, new| sink.borrow_mut().push((old, new)));
+
+        lru.add_many((1..=4).map(|i| (i, i)).collect());
+        assert_eq!(lru.resize(2), vec![(1, 1), (2, 2)]);
+        lru.set_capacity(2);
+        lru.grow(3);
+        lru.clear_and_resize(1);
+        assert_eq!(*changes.borrow(), vec![(4, 2), (2, 5), (5, 1)]);
+    }
+
+    #[test]
+    fn drain_over_capacity_flushes_the_overflow() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+        lru.add_many(vec![(1, 1), (2, 2)]);
+        lru.pin(&1);
+        lru.pin(&2);
+        // every entry is pinned, so the cache grows past its capacity
+        assert_eq!(lru.add_many(vec![(3, 3), (4, 4)]), vec![(3, 3)]);
+        lru.pin(&4);
+        lru.add(5, 5);
+        assert_eq!(lru.len, 4);
+
+        lru.unpin(&1);
+        lru.unpin(&2);
+        assert_eq!(lru.drain_over_capacity(), vec![(1, 1), (2, 2)]);
+        assert_eq!(lru.len, 2);
+        assert!(lru.drain_over_capacity().is_empty());
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn shrink_to_keeps_the_cache_working() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(100);
+        lru.reserve(1000);
+        lru.add_many((0..10).map(|i| (i, i)).collect());
+
+        lru.shrink_to(20);
+        assert!(lru.raw_capacity() >= 20);
+        assert!(lru.raw_capacity() < 1000);
+
+        lru.shrink_to(0);
+        assert!(lru.raw_capacity() >= lru.len);
+        assert_eq!(lru.get(&3), Some(3));
+        lru.add_many((10..100).map(|i| (i, i)).collect());
+        assert_eq!(lru.len, 100);
+        assert_eq!(lru.add(100, 100), Some((0, 0)));
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn insert_ordered_takes_the_lru_entry_first() {
+        let entries = vec![(1, 10), (2, 20), (3, 30)];
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        lru.add(9, 90);
+        lru.insert_ordered(entries.clone());
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1, 9]);
+        assert!(lru.is_mru(&3));
+
+        // from_ordered reads the same list the other way round
+        let lru: LRUCache<u64, u64> = LRUCache::from_ordered(4, entries);
+        assert_eq!(lru.keys_snapshot(), vec![1, 2, 3]);
+
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+        lru.insert_ordered(vec![(1, 10), (2, 20), (3, 30)]);
+        assert_eq!(lru.to_index_ordered(), vec![(3, 30), (2, 20)]);
+    }
+
+    #[test]
+    fn recent_hit_ratio_tracks_the_window() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(10);
+        assert_eq!(lru.recent_hit_ratio(), 0.0);
+        lru.set_window(4);
+        lru.add_many((0..10).map(|i| (i, i)).collect());
+
+        // a long run of hits followed by a phase of misses
+        for i in 0..100 {
+            lru.get(&(i % 10));
+        }
+        assert_eq!(lru.recent_hit_ratio(), 1.0);
+        for i in 0..3 {
+            lru.get(&(100 + i));
+        }
+        // lifetime ratio would be 100 / 103
+        assert_eq!(lru.recent_hit_ratio(), 0.25);
+        lru.get(&200);
+        assert_eq!(lru.recent_hit_ratio(), 0.0);
+
+        lru.set_window(0);
+        lru.get(&1);
+        assert_eq!(lru.recent_hit_ratio(), 0.0);
+    }
+
+    #[test]
+    fn entries_in_recency_order() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(4);
+        assert!(lru.entries().is_empty());
+
+        lru.add_many(vec![(1, 10), (2, 20), (3, 30)]);
+        lru.get(&1);
+        let entries = lru.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries, vec![(&1, &10), (&3, &30), (&2, &20)]);
+        assert_eq!(&entries[1..], &[(&3, &30), (&2, &20)]);
+        assert!(lru.is_lru(&2));
+    }
+
+    #[test]
+    fn is_fresh_flips_on_expiry() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+        lru.add(1, 1);
+        lru.add_with_ttl(2, 2, Duration::from_millis(20));
+
+        assert!(lru.is_fresh(&1));
+        assert!(lru.is_fresh(&2));
+        assert!(!lru.is_fresh(&3));
+
+        sleep(Duration::from_millis(30));
+        assert!(lru.is_fresh(&1));
+        assert!(!lru.is_fresh(&2));
+        // the expired entry is still stored until a lookup removes it
+        assert_eq!(lru.len, 2);
+    }
+
+    #[test]
+    fn evict_lru_into_streams_the_oldest() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(5);
+        lru.add_many((1..=5).map(|i| (i, i)).collect());
+        lru.get(&1);
+        lru.pin(&3);
+
+        let mut seen = Vec::new();
+        assert_eq!(lru.evict_lru_into(2, |key, _| seen.push(key)), 2);
+        assert_eq!(seen, vec![2, 4]);
+
+        seen.clear();
+        assert_eq!(lru.evict_lru_into(10, |key, _| seen.push(key)), 2);
+        assert_eq!(seen, vec![5, 1]);
+        assert_eq!(lru.keys_snapshot(), vec![3]);
+    }
+
+    #[test]
+    fn into_parts_round_trips_from_ordered() {
+        let entries = vec![(3, 30), (1, 10), (2, 20)];
+        let lru: LRUCache<u64, u64> = LRUCache::from_ordered(5, entries.clone());
+        assert_eq!(lru.into_parts(), (5, entries));
+
+        let value = Rc::new(());
+        let mut lru: LRUCache<u64, Rc<()>> = LRUCache::new(2);
+        lru.add(1, Rc::clone(&value));
+        let (cap, entries) = lru.into_parts();
+        assert_eq!((cap, entries.len()), (2, 1));
+        drop(entries);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn promote_where_keeps_relative_order() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(6);
+        lru.add_many((1..=6).map(|i| (i, i)).collect());
+
+        assert_eq!(lru.promote_where(|_, value| value % 2 == 0), 3);
+        assert_eq!(lru.keys_snapshot(), vec![6, 4, 2, 5, 3, 1]);
+
+        assert_eq!(lru.promote_where(|key, _| *key == 1 || *key == 3), 2);
+        assert_eq!(lru.keys_snapshot(), vec![3, 1, 6, 4, 2, 5]);
+        assert_eq!(lru.promote_where(|_, _| false), 0);
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn remove_where_returns_the_removed() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(6);
+        lru.add_many((1..=6).map(|i| (i, i * 10 + i % 2)).collect());
+
+        assert_eq!(
+            lru.remove_where(|_, value| value % 2 == 1),
+            vec![(5, 51), (3, 31), (1, 11)]
+        );
+        assert_eq!(lru.to_index_ordered(), vec![(6, 60), (4, 40), (2, 20)]);
+        assert!(lru.remove_where(|_, value| value % 2 == 1).is_empty());
+        lru.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn touch_or_insert_keeps_stored_values() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(2);
+        lru.add_many(vec![(1, 10), (2, 20)]);
+
+        assert!(lru.touch_or_insert(1, 0));
+        assert_eq!(lru.peek_mru(), Some((&1, &10)));
+
+        assert!(!lru.touch_or_insert(3, 30));
+        assert_eq!(lru.to_index_ordered(), vec![(3, 30), (1, 10)]);
+    }
+
+    #[test]
+    fn get_or_insert_with_evicts_only_unpinned() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(3);
+        lru.add_many(vec![(1, 1), (2, 2), (3, 3)]);
+        lru.pin(&1);
+        lru.pin(&3);
+
+        let (value, evicted) = lru.get_or_insert_with_evicted(4, || 4);
+        assert_eq!((value, evicted), (&4, Some((2, 2))));
+        assert_eq!(lru.keys_snapshot(), vec![4, 3, 1]);
+
+        lru.pin(&4);
+        assert_eq!(
+            lru.try_get_or_insert_with(5, || unreachable!()),
+            Err(LruError::AllPinned)
+        );
+        assert_eq!(lru.try_get_or_insert_with(1, || unreachable!()), Ok(&1));
+        assert_eq!(lru.len, 3);
+
+        // without the try_ variant the cache grows past its capacity
+        assert_eq!(lru.get_or_insert_with(5, || 5), &5);
+        assert_eq!(lru.len, 4);
+
+        let mut empty: LRUCache<u64, u64> = LRUCache::new(0);
+        assert_eq!(
+            empty.try_get_or_insert_with(1, || 1),
+            Err(LruError::ZeroCapacity)
+        );
+    }
+
+    #[test]
+    fn try_get_or_insert_with_respects_pinned_weight() {
+        let mut lru: LRUCache<u64, Vec<u8>> =
+            LRUCache::with_limits(10, 10, |_, value: &Vec<u8>| value.len());
+        lru.add_many(vec![(1, vec![0; 4]), (2, vec![0; 4]), (3, vec![0; 2])]);
+        lru.pin(&1);
+        lru.pin(&2);
+
+        // evicting 3 frees 2, the pinned 8 leave no room for 3 more
+        assert_eq!(
+            lru.try_get_or_insert_with(4, || vec![0; 3]),
+            Err(LruError::AllPinned)
+        );
+        assert_eq!(
+            lru.try_get_or_insert_with(4, || vec![0; 11]),
+            Err(LruError::ItemTooLarge)
+        );
+        assert_eq!(lru.keys_snapshot(), vec![3, 2, 1]);
+        assert_eq!(lru.weight(), 10);
+
+        assert_eq!(
+            lru.try_get_or_insert_with(4, || vec![0; 2]),
+            Ok(&vec![0; 2])
+        );
+        assert_eq!(lru.keys_snapshot(), vec![4, 2, 1]);
+        assert_eq!(lru.weight(), 10);
+        lru.check_invariants().unwrap();
+    }
+
+    struct RecordingTimer(Rc<RefCell<Vec<(OpKind, Duration)>>>);
+
+    impl Timer for RecordingTimer {
+        fn record(&self, op: OpKind, duration: Duration) {
+            self.0.borrow_mut().push((op, duration));
+        }
+    }
+
+    #[test]
+    fn timer_records_operations() {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let mut lru: LRUCache<u64, u64> =
+            LRUCache::new(2).with_timer(RecordingTimer(Rc::clone(&records)));
+
+        lru.add(1, 1);
+        let (op, duration) = records.borrow()[0];
+        assert_eq!(op, OpKind::Add);
+        assert!(duration > Duration::ZERO);
+
+        lru.get(&1);
+        lru.peek(&2);
+        lru.remove(&1);
+        lru.resize(4);
+        lru.clear();
+        let ops: Vec<_> = records.borrow().iter().map(|(op, _)| *op).collect();
+        assert_eq!(
+            ops,
+            vec![
+                OpKind::Add,
+                OpKind::Get,
+                OpKind::Peek,
+                OpKind::Remove,
+                OpKind::Resize,
+                OpKind::Clear,
+            ]
+        );
+    }
+
+    #[test]
+    fn timer_records_each_call_once() {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let mut lru: LRUCache<u64, u64> =
+            LRUCache::new(4).with_timer(RecordingTimer(Rc::clone(&records)));
+
+        lru.add_with_ttl(1, 1, Duration::ZERO);
+        assert_eq!(lru.get(&1), None);
+        lru.add(2, 2);
+        lru.get_ref(&2);
+        lru.get_mut(&2);
+        lru.get_or(&3, 3);
+        lru.try_get(&2);
+        lru.get_or_insert_with(3, || 3);
+        lru.try_get_or_insert_with(4, || 4).unwrap();
+        lru.get_or_insert_default(5);
+        let ops: Vec<_> = records.borrow().iter().map(|(op, _)| *op).collect();
+        assert_eq!(
+            ops,
+            vec![
+                OpKind::Add,
+                OpKind::Get,
+                OpKind::Add,
+                OpKind::Get,
+                OpKind::Get,
+                OpKind::Get,
+                OpKind::Get,
+                OpKind::Get,
+                OpKind::Get,
+                OpKind::Get,
+            ]
+        );
+    }
+
+    #[test]
+    fn reserve_for_fill_then_evict() {
+        let mut lru: LRUCache<u64, u64> = LRUCache::new(64);
+        lru.add_many((0..10).map(|i| (i, i)).collect());
+        lru.reserve_for_fill();
+        let raw_capacity = lru.raw_capacity();
+        assert!(raw_capacity >= 64);
+
+        lru.add_many((10..64).map(|i| (i, i)).collect());
+        assert_eq!(lru.raw_capacity(), raw_capacity);
+        assert_eq!(lru.len, 64);
+
+        assert_eq!(lru.add(64, 64), Some((0, 0)));
+        assert_eq!(lru.get(&1), Some(1));
+        assert_eq!(lru.add(65, 65), Some((2, 2)));
+        assert_eq!(lru.len, 64);
+        lru.check_invariants().unwrap();
     }
 }
@@ -0,0 +1,101 @@
+//! LRU cache split into namespaces
+
+use std::{fmt, hash::Hash};
+
+use crate::lru::LRUCache;
+
+/// LRU cache keyed by `(namespace, key)`, so several logical caches share one
+/// capacity budget while each namespace can be invalidated on its own
+pub struct NamespacedLRUCache<N, K, V>
+where
+    N: Hash + Eq + Clone,
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    lru: LRUCache<(N, K), V>,
+}
+
+impl<N, K, V> fmt::Debug for NamespacedLRUCache<N, K, V>
+where
+    N: Hash + Eq + Clone,
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamespacedLRUCache")
+            .field("lru", &self.lru)
+            .finish()
+    }
+}
+
+impl<N, K, V> NamespacedLRUCache<N, K, V>
+where
+    N: Hash + Eq + Clone,
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// creates new instance of the cache with the capacity shared by all
+    /// namespaces
+    /// # `Arguments`
+    /// - `cap`-> capacity
+    /// # `Returns`
+    /// - Self
+    pub fn new(cap: usize) -> Self {
+        Self {
+            lru: LRUCache::new(cap),
+        }
+    }
+
+    /// adds new key to the namespace, see `LRUCache::add`
+    pub fn add(&mut self, ns: N, key: K, value: V) -> Option<((N, K), V)> {
+        self.lru.add((ns, key), value)
+    }
+
+    /// get value associated with the key in the namespace, see `LRUCache::get`
+    pub fn get(&mut self, ns: &N, key: &K) -> Option<V> {
+        self.lru.get(&(ns.clone(), key.clone()))
+    }
+
+    /// removes every entry of the namespace, leaving the others untouched
+    /// # `Arguments`
+    /// - `ns` -> namespace to clear
+    pub fn clear_namespace(&mut self, ns: &N) {
+        self.lru.retain(|(entry_ns, _), _| entry_ns != ns);
+    }
+
+    /// underlying cache keyed by `(namespace, key)`
+    pub fn inner(&self) -> &LRUCache<(N, K), V> {
+        &self.lru
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::lru::NamespacedLRUCache;
+
+    #[test]
+    fn clear_one_namespace() {
+        let mut lru: NamespacedLRUCache<&str, u64, u64> = NamespacedLRUCache::new(4);
+
+        lru.add("users", 1, 10);
+        lru.add("posts", 1, 100);
+        lru.add("users", 2, 20);
+        assert_eq!(lru.get(&"users", &1), Some(10));
+        assert_eq!(lru.get(&"posts", &1), Some(100));
+
+        lru.clear_namespace(&"users");
+        assert_eq!(lru.get(&"users", &1), None);
+        assert_eq!(lru.get(&"users", &2), None);
+        assert_eq!(lru.get(&"posts", &1), Some(100));
+        assert_eq!(lru.inner().keys_snapshot(), vec![("posts", 1)]);
+    }
+
+    #[test]
+    fn namespaces_share_the_capacity() {
+        let mut lru: NamespacedLRUCache<u8, u64, u64> = NamespacedLRUCache::new(2);
+
+        lru.add(0, 1, 1);
+        lru.add(1, 1, 1);
+        assert_eq!(lru.add(2, 1, 1), Some(((0, 1), 1)));
+    }
+}
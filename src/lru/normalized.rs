@@ -0,0 +1,98 @@
+//! LRU cache with normalized keys
+
+use std::{fmt, hash::Hash};
+
+use crate::lru::LRUCache;
+
+/// Normalizer mapping a key onto the form it is stored under
+type Normalizer<K> = Box<dyn Fn(&K) -> K>;
+
+/// LRU cache storing every key in a normalized form, so keys that normalize
+/// to the same value (e.g. case-insensitive strings) hit the same entry
+pub struct NormalizedLRUCache<K: Hash + Eq + Clone, V: Clone> {
+    lru: LRUCache<K, V>,
+    normalize: Normalizer<K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> fmt::Debug for NormalizedLRUCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NormalizedLRUCache")
+            .field("lru", &self.lru)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> NormalizedLRUCache<K, V> {
+    /// creates new instance of the cache with the capacity
+    /// # `Arguments`
+    /// - `cap`-> capacity
+    /// - `normalize` -> applied to keys on every add, get, peek and remove
+    /// # `Returns`
+    /// - Self
+    pub fn with_key_normalizer<F>(cap: usize, normalize: F) -> Self
+    where
+        F: Fn(&K) -> K + 'static,
+    {
+        Self {
+            lru: LRUCache::new(cap),
+            normalize: Box::new(normalize),
+        }
+    }
+
+    /// adds new key to the cache, see `LRUCache::add`
+    pub fn add(&mut self, key: K, value: V) -> Option<(K, V)> {
+        let key = (self.normalize)(&key);
+        self.lru.add(key, value)
+    }
+
+    /// get value associated with the key, see `LRUCache::get`
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let key = (self.normalize)(key);
+        self.lru.get(&key)
+    }
+
+    /// get value associated with the key without promoting it, see `LRUCache::peek`
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        let key = (self.normalize)(key);
+        self.lru.peek(&key)
+    }
+
+    /// checks if the key exists in the cache, see `LRUCache::contains_key`
+    pub fn contains_key(&self, key: &K) -> bool {
+        let key = (self.normalize)(key);
+        self.lru.contains_key(&key)
+    }
+
+    /// removes the key from the cache, see `LRUCache::remove`
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let key = (self.normalize)(key);
+        self.lru.remove(&key)
+    }
+
+    /// underlying cache holding the normalized keys
+    pub fn inner(&self) -> &LRUCache<K, V> {
+        &self.lru
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::lru::NormalizedLRUCache;
+
+    #[test]
+    fn case_insensitive_keys() {
+        let mut lru: NormalizedLRUCache<String, u64> =
+            NormalizedLRUCache::with_key_normalizer(2, |key: &String| key.to_lowercase());
+
+        lru.add("Foo".to_string(), 1);
+        assert_eq!(lru.get(&"foo".to_string()), Some(1));
+        assert!(lru.contains_key(&"FOO".to_string()));
+
+        assert_eq!(lru.add("foo".to_string(), 2), None);
+        assert_eq!(lru.peek(&"Foo".to_string()), Some(&2));
+        assert_eq!(lru.inner().keys_snapshot(), vec!["foo".to_string()]);
+
+        assert_eq!(lru.remove(&"fOo".to_string()), Some(2));
+        assert!(!lru.contains_key(&"foo".to_string()));
+    }
+}
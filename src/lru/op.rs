@@ -0,0 +1,24 @@
+//! Operations
+
+/// A single cache operation, applied with `LRUCache::apply_op`. Lets a
+/// fuzzer or a model-based test drive the cache with a stream of decoded
+/// operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<K, V> {
+    /// `add(key, value)`
+    Add(K, V),
+    /// `get(key)`
+    Get(K),
+    /// `peek(key)`
+    Peek(K),
+    /// `remove(key)`
+    Remove(K),
+    /// `peek_mru()`
+    PeekMru,
+    /// `peek_lru()`
+    PeekLru,
+    /// `clear()`
+    Clear,
+    /// `resize(cap)`
+    Resize(usize),
+}
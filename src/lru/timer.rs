@@ -0,0 +1,27 @@
+//! Timing hooks
+
+use std::time::Duration;
+
+/// Operation of the LRU cache whose duration is handed to a `Timer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpKind {
+    /// `add` and `add_with_ttl`
+    Add,
+    /// `get` and the other promoting lookups, see `LRUCache::with_timer`
+    Get,
+    /// `peek`
+    Peek,
+    /// `remove`
+    Remove,
+    /// `clear`
+    Clear,
+    /// `set_capacity` and `resize`
+    Resize,
+}
+
+/// Receives the duration of the operations of an LRU cache, e.g. to feed a
+/// latency histogram of an external metrics backend
+pub trait Timer {
+    /// an operation of the given kind took `duration`
+    fn record(&self, op: OpKind, duration: Duration);
+}
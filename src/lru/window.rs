@@ -0,0 +1,64 @@
+//! Sliding window of lookup outcomes
+
+/// Ring buffer holding whether each of the last lookups was a hit
+#[derive(Debug, Clone)]
+pub(crate) struct HitWindow {
+    outcomes: Vec<bool>,
+    next: usize,
+    filled: usize,
+    hits: usize,
+}
+
+impl HitWindow {
+    /// creates new empty window of `size` lookups, `size` must not be zero
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            outcomes: vec![false; size],
+            next: 0,
+            filled: 0,
+            hits: 0,
+        }
+    }
+
+    /// records a lookup, pushing the oldest one out of a full window
+    pub(crate) fn record(&mut self, hit: bool) {
+        if self.filled == self.outcomes.len() {
+            self.hits -= self.outcomes[self.next] as usize;
+        } else {
+            self.filled += 1;
+        }
+        self.outcomes[self.next] = hit;
+        self.hits += hit as usize;
+        self.next = (self.next + 1) % self.outcomes.len();
+    }
+
+    /// share of hits among the recorded lookups, 0 when there are none
+    pub(crate) fn hit_ratio(&self) -> f64 {
+        if self.filled == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.filled as f64
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use crate::lru::window::HitWindow;
+
+    #[test]
+    fn oldest_outcomes_fall_out() {
+        let mut window = HitWindow::new(3);
+        assert_eq!(window.hit_ratio(), 0.0);
+
+        window.record(true);
+        window.record(false);
+        assert_eq!(window.hit_ratio(), 0.5);
+
+        window.record(true);
+        window.record(false);
+        window.record(false);
+        assert_eq!(window.hit_ratio(), 1.0 / 3.0);
+        window.record(false);
+        assert_eq!(window.hit_ratio(), 0.0);
+    }
+}
@@ -0,0 +1,65 @@
+//! checks that every allocation made by the caches is freed again
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use lru_rs::lru::LRUCache;
+
+struct CountingAlloc;
+
+thread_local! {
+    // allocations minus deallocations of the current thread, kept per thread
+    // so tests running in parallel do not see each other
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        _ = LIVE.try_with(|live| live.set(live.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        _ = LIVE.try_with(|live| live.set(live.get() - 1));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// net number of allocations `f` leaves behind on the current thread
+fn leaked_by(f: impl FnOnce()) -> isize {
+    let before = LIVE.with(Cell::get);
+    f();
+    LIVE.with(Cell::get) - before
+}
+
+#[test]
+fn new_and_drop_is_balanced() {
+    assert_eq!(leaked_by(|| drop(LRUCache::<u64, u64>::new(4))), 0);
+}
+
+#[test]
+fn filled_cache_drop_is_balanced() {
+    let leaked = leaked_by(|| {
+        let mut lru: LRUCache<u64, String> = LRUCache::new(4);
+        for i in 0..10 {
+            lru.add(i, i.to_string());
+        }
+        lru.remove(&9);
+    });
+    assert_eq!(leaked, 0);
+}
+
+#[test]
+fn into_parts_is_balanced() {
+    let leaked = leaked_by(|| {
+        let lru: LRUCache<u64, u64> = LRUCache::from_ordered(4, vec![(1, 1), (2, 2)]);
+        let (_, entries) = lru.into_parts();
+        drop(entries);
+    });
+    assert_eq!(leaked, 0);
+}